@@ -0,0 +1,158 @@
+use std::hash::{Hash, Hasher};
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+
+// Cycle detection for sequences of states, where each state is a step from the last
+
+// A sequence x0, f(x0), f(f(x0)).. enters a loop of length lambda after a tail of length mu
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cycle {
+    pub mu: usize,
+    pub lambda: usize,
+}
+
+impl Cycle {
+    // Number of steps from the start which reaches the same state as n steps; an empty loop
+    // (as for an empty sequence) never repeats, so nothing reduces
+    pub fn reduce(&self, n: usize) -> usize {
+        if n < self.mu || self.lambda == 0 {
+            n
+        } else {
+            self.mu + (n - self.mu) % self.lambda
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    // Keeps only two states, but steps through the sequence about three times
+    Brent,
+    // Remembers every state, but steps through the sequence once
+    Hashing,
+    // Remembers a 64-bit hash of every state, replaying from the start to rule out collisions
+    Fingerprint,
+}
+
+impl Method {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "brent" => Some(Method::Brent),
+            "hashing" => Some(Method::Hashing),
+            "fingerprint" => Some(Method::Fingerprint),
+            _ => None,
+        }
+    }
+}
+
+// Brent's algorithm: the hare searches for the tortoise, which teleports to the hare at powers of two;
+// states only need comparing, not hashing
+pub fn brent<T, F>(start: &T, mut step: F) -> Cycle
+    where T: Clone + PartialEq, F: FnMut(&mut T) {
+    let mut power = 1;
+    let mut lambda = 1;
+    let mut tortoise = start.clone();
+    let mut hare = start.clone();
+    step(&mut hare);
+    while tortoise != hare {
+        if power == lambda {
+            tortoise.clone_from(&hare);
+            power *= 2;
+            lambda = 0;
+        }
+        step(&mut hare);
+        lambda += 1;
+    }
+    debug_assert!(lambda > 0);
+
+    // Start the hare lambda steps ahead, so the two meet at the start of the loop
+    let mut mu = 0;
+    tortoise.clone_from(start);
+    hare.clone_from(start);
+    for _ in 0..lambda {
+        step(&mut hare);
+    }
+    while tortoise != hare {
+        step(&mut tortoise);
+        step(&mut hare);
+        mu += 1;
+    }
+
+    Cycle { mu, lambda }
+}
+
+// Remember when each state was first seen, so the first repeat gives both mu and lambda
+fn hashing<T, F>(start: &T, mut step: F) -> Cycle
+    where T: Clone + Eq + Hash, F: FnMut(&mut T) {
+    let mut seen = HashMap::new();
+    let mut state = start.clone();
+    let mut steps = 0;
+    loop {
+        if let Some(&first) = seen.get(&state) {
+            return Cycle { mu: first, lambda: steps - first };
+        }
+        seen.insert(state.clone(), steps);
+        step(&mut state);
+        steps += 1;
+    }
+}
+
+fn fingerprint<T: Hash>(state: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    state.hash(&mut hasher);
+    hasher.finish()
+}
+
+// Like hashing, but only keeps the fingerprints of states, and the steps where they were seen
+fn fingerprinting<T, F>(start: &T, mut step: F) -> Cycle
+    where T: Clone + Eq + Hash, F: FnMut(&mut T) {
+    let mut seen: HashMap<u64, Vec<usize>> = HashMap::new();
+    let mut state = start.clone();
+    let mut steps = 0;
+    loop {
+        let firsts = seen.entry(fingerprint(&state)).or_default();
+
+        // Replay from the start to check each earlier state with the same fingerprint
+        for &first in firsts.iter() {
+            let mut replay = start.clone();
+            for _ in 0..first {
+                step(&mut replay);
+            }
+            if replay == state {
+                return Cycle { mu: first, lambda: steps - first };
+            }
+        }
+
+        firsts.push(steps);
+        step(&mut state);
+        steps += 1;
+    }
+}
+
+// Find the cycle of repeatedly applying step to a copy of start, with any method; states which
+// can't be hashed can still use brent directly
+pub fn find_cycle<T, F>(start: &T, step: F, method: Method) -> Cycle
+    where T: Clone + Eq + Hash, F: FnMut(&mut T) {
+    match method {
+        Method::Brent => brent(start, step),
+        Method::Hashing => hashing(start, step),
+        Method::Fingerprint => fingerprinting(start, step),
+    }
+}
+
+#[test]
+fn test_cycle_find() {
+    // 3 -> 10 -> 101 -> 2 -> 5 -> 26 -> 167 -> 95 -> 101, etc
+    let step = |x: &mut u32| *x = (*x * *x + 1) % 255;
+    for &method in &[Method::Brent, Method::Hashing, Method::Fingerprint] {
+        assert_eq!(find_cycle(&3, step, method), Cycle { mu: 2, lambda: 6 });
+        assert_eq!(find_cycle(&0, |x: &mut u32| *x = (*x + 1) % 7, method), Cycle { mu: 0, lambda: 7 });
+        assert_eq!(find_cycle(&9, |x: &mut u32| *x = 4, method), Cycle { mu: 1, lambda: 1 });
+    }
+
+    // Floats can't be hashed, but can be compared: 0.375 -> 0.75 -> 0.5 -> 0 -> 0
+    assert_eq!(brent(&0.375, |x: &mut f64| *x = *x * 2.0 % 1.0), Cycle { mu: 3, lambda: 1 });
+
+    let cycle = Cycle { mu: 2, lambda: 6 };
+    assert_eq!((cycle.reduce(1), cycle.reduce(7), cycle.reduce(8), cycle.reduce(1000)), (1, 7, 2, 4));
+    assert_eq!(Cycle { mu: 0, lambda: 0 }.reduce(5), 5);
+}
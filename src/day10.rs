@@ -1,6 +1,10 @@
 use std::fs;
 use std::env;
 use std::io;
+use std::io::Read;
+use std::hash::Hasher;
+use std::hint::black_box;
+use std::time::Instant;
 
 // Run a single iteration of the hash function
 fn do_hash(size: usize, lengths: &[u8], list: &mut [usize], position_skip: &mut (usize, usize)) {
@@ -38,26 +42,114 @@ fn do_hash(size: usize, lengths: &[u8], list: &mut [usize], position_skip: &mut
     }
 }
 
+// Settings for variants of the knot hash
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KnotHashParams {
+    // Number of elements in the ring, at most 256 so each fits a byte
+    pub size: usize,
+    pub rounds: usize,
+    // Lengths appended to every input
+    pub suffix: Vec<u8>,
+    // Number of elements XORed together into each byte of the dense hash
+    pub block: usize,
+}
+
+impl Default for KnotHashParams {
+    fn default() -> Self {
+        KnotHashParams { size: 256, rounds: 64, suffix: vec![17, 31, 73, 47, 23], block: 16 }
+    }
+}
+
+impl KnotHashParams {
+    // Check these settings can hash the given input
+    fn check(&self, input: &[u8]) -> Result<(), io::Error> {
+        let invalid = |message: String| Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        if self.size < 2 || self.size > 256 {
+            return invalid(format!("Ring size {} must be from 2 to 256", self.size));
+        }
+        if self.block == 0 || !self.size.is_multiple_of(self.block) {
+            return invalid(format!("Block size {} must divide ring size {}", self.block, self.size));
+        }
+        match input.iter().chain(&self.suffix).find(|&&length| length as usize > self.size) {
+            Some(length) => invalid(format!("Length {} is longer than ring size {}", length, self.size)),
+            None => Ok(()),
+        }
+    }
+}
+
 // Compute the sparse hash for a given input
-fn sparse_hash(size: usize, lengths: &[u8]) -> Vec<usize> {
-    assert!(size >= 2);
+fn sparse_hash(params: &KnotHashParams, lengths: &[u8]) -> Vec<usize> {
+    assert!(params.size >= 2);
 
     // Initialize list with values incrementing from zero
-    let mut list: Vec<usize> = (0..size).collect();
+    let mut list: Vec<usize> = (0..params.size).collect();
 
-    // Run 64 iterations of the hash function
+    // Run each round of the hash function
     let mut position_skip = (0, 0);
-    for _ in 0..64 {
-        do_hash(size, lengths, &mut list, &mut position_skip);
+    for _ in 0..params.rounds {
+        do_hash(params.size, lengths, &mut list, &mut position_skip);
     }
 
     list
 }
 
+// Same as sparse_hash, but rotating the ring to keep the current position at the front,
+// so each length reverses a plain slice without wrapping around
+fn fast_sparse_hash(params: &KnotHashParams, lengths: &[u8]) -> Vec<u8> {
+    let size = params.size;
+    assert!((2..=256).contains(&size));
+
+    // Initialize list with values incrementing from zero
+    let mut buffer = [0u8; 256];
+    for (i, v) in buffer.iter_mut().enumerate() {
+        *v = i as u8;
+    }
+    let ring = &mut buffer[..size];
+
+    // Track how far the ring is rotated from where sparse_hash would have it
+    let mut rotation = 0;
+    let mut skip = 0;
+    for _ in 0..params.rounds {
+        for &length in lengths {
+            let length = length as usize;
+            assert!(length <= size);
+
+            ring[..length].reverse();
+            let step = (length + skip) % size;
+            ring.rotate_left(step);
+            rotation = (rotation + step) % size;
+            skip = (skip + 1) % size;
+        }
+    }
+
+    // Undo the rotation
+    ring.rotate_right(rotation);
+    ring.to_vec()
+}
+
+#[test]
+fn test_day10_fast() {
+    // Compare against sparse_hash with lengths from a simple LCG
+    let mut seed: u64 = 10;
+    let mut random = |range: u64| {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (seed >> 33) % range
+    };
+    for &size in &[2, 3, 5, 16, 255, 256] {
+        for rounds in 0..4 {
+            let params = KnotHashParams { size, rounds, ..KnotHashParams::default() };
+            let count = random(100);
+            let lengths: Vec<u8> = (0..count).map(|_| random(size as u64 + 1) as u8).collect();
+            let expected: Vec<u8> = sparse_hash(&params, &lengths).into_iter().map(|i| i as u8).collect();
+            assert_eq!(fast_sparse_hash(&params, &lengths), expected);
+        }
+    }
+}
+
 // Compute the dense hash for a given input
-fn dense_hash(size: usize, lengths: &[u8]) -> Vec<usize> {
+fn dense_hash(params: &KnotHashParams, lengths: &[u8]) -> Vec<u8> {
     // Compute the sparse hash first
-    let sparse = sparse_hash(size, lengths);
+    let sparse = fast_sparse_hash(params, lengths);
 
     // Mutable state for filter_map
     let mut hash = 0;
@@ -67,9 +159,9 @@ fn dense_hash(size: usize, lengths: &[u8]) -> Vec<usize> {
     sparse
         .iter()
         .filter_map(|i| {
-            // Reduce by XORing over blocks of 16, yielding 1 out per 16 in
+            // Reduce by XORing over blocks, yielding 1 out per block in
             hash = if count == 0 { *i } else { hash ^ *i };
-            count = (count + 1) % 16;
+            count = (count + 1) % params.block;
             if count == 0 { Some(hash) } else { None }
         })
         .collect()
@@ -99,28 +191,112 @@ fn test_day10_part1() {
     assert_eq!(part1(5, "3,4,1,5"), 12);
 }
 
-pub fn knot_hash(input: &str) -> String {
-    // We must discard the EOL whitespace
-    let input = input.trim_right();
+// Collects input to be knot hashed, since every round runs over the whole input
+#[derive(Clone, Default)]
+pub struct KnotHasher {
+    input: Vec<u8>,
+    params: KnotHashParams,
+}
 
-    // We want ASCII, but UTF-8 will have to do
-    assert_eq!(input.len(), input.chars().count(), "Multi-byte UTF-8 chars are not allowed");
-    let mut lengths = input.to_string().into_bytes();
+impl KnotHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_params(params: KnotHashParams) -> Self {
+        KnotHasher { input: Vec::new(), params }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        self.input.extend_from_slice(bytes);
+    }
+
+    // Dense hash with any parameters, one byte per block
+    pub fn digest(&self) -> Result<Vec<u8>, io::Error> {
+        self.params.check(&self.input)?;
+
+        // Append the given magic numbers
+        let mut lengths = self.input.clone();
+        lengths.extend_from_slice(&self.params.suffix);
+
+        Ok(dense_hash(&self.params, &lengths))
+    }
+
+    // Panics unless the parameters give a 16 byte hash, as the defaults do
+    pub fn finalize(&self) -> [u8; 16] {
+        let mut hash = [0; 16];
+        hash.copy_from_slice(&self.digest().expect("invalid knot hash parameters"));
+        hash
+    }
+
+    pub fn finalize_hex(&self) -> String {
+        to_hex(&self.finalize())
+    }
+}
 
-    // Append the given magic numbers
-    lengths.extend_from_slice(&[17, 31, 73, 47, 23]);
+// Hashing with the first 64 bits of the knot hash
+impl Hasher for KnotHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        self.update(bytes);
+    }
 
-    // Compute the dense hash (given size 256)
-    let dense = dense_hash(256, &lengths);
+    fn finish(&self) -> u64 {
+        let hash = self.finalize();
+        let mut first = [0; 8];
+        first.copy_from_slice(&hash[..8]);
+        u64::from_be_bytes(first)
+    }
+}
 
-    // Convert dense hash to hex string
-    let mut hex = String::with_capacity(32);
-    for i in &dense {
-        hex.push_str(&format!("{:02x}", *i as u8));
+pub fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        hex.push_str(&format!("{:02x}", byte));
     }
     hex
 }
 
+#[test]
+fn test_day10_hasher() {
+    let mut hasher = KnotHasher::new();
+    hasher.update(b"AoC");
+    hasher.update(b" 2017");
+    assert_eq!(hasher.finalize_hex(), "33efeb34ea91902bb2f59c9920caa6cd");
+    assert_eq!(hasher.finalize()[..4], [0x33, 0xef, 0xeb, 0x34]);
+    assert_eq!(hasher.finish(), 0x33efeb34ea91902b);
+
+    // Any bytes are fine, not just ASCII
+    let mut hasher = KnotHasher::new();
+    hasher.write(&[0, 255, 0xc3, 0x28]);
+    assert_eq!(hasher.finalize_hex().len(), 32);
+    assert_ne!(hasher.finalize(), KnotHasher::new().finalize());
+}
+
+#[test]
+fn test_day10_params() {
+    // Part 1 is a single round with no suffix and no XORing
+    let params = KnotHashParams { size: 5, rounds: 1, suffix: vec![], block: 1 };
+    let mut hasher = KnotHasher::with_params(params.clone());
+    hasher.update(&[3, 4, 1, 5]);
+    assert_eq!(hasher.digest().unwrap(), vec![3, 4, 2, 1, 0]);
+
+    // Lengths can't be longer than the ring
+    hasher.update(&[6]);
+    assert!(hasher.digest().is_err());
+    assert!(KnotHasher::with_params(KnotHashParams { block: 2, ..params }).digest().is_err());
+
+    let mut hasher = KnotHasher::with_params(KnotHashParams { block: 32, ..KnotHashParams::default() });
+    hasher.update(b"AoC 2017");
+    assert_eq!(hasher.digest().unwrap().len(), 8);
+}
+
+pub fn knot_hash(input: &str) -> String {
+    // We must discard the EOL whitespace
+    let mut hasher = KnotHasher::new();
+    hasher.update(input.trim_end().as_bytes());
+    hasher.finalize_hex()
+}
+
 #[test]
 fn test_day10_part2() {
     assert_eq!(knot_hash(""), "a2582a3a0e66e6e86e3812dcb672a272");
@@ -129,6 +305,97 @@ fn test_day10_part2() {
     assert_eq!(knot_hash("1,2,4"), "63960835bcdc130f0b66d7ff4f6a5a8e");
 }
 
+// Time sparse_hash against fast_sparse_hash, failing if their results differ
+fn bench(params: &KnotHashParams, input: &[u8], runs: u32) -> Result<String, io::Error> {
+    params.check(input)?;
+    let mut lengths = input.to_vec();
+    lengths.extend_from_slice(&params.suffix);
+
+    let expected: Vec<u8> = sparse_hash(params, &lengths).into_iter().map(|i| i as u8).collect();
+    let actual = fast_sparse_hash(params, &lengths);
+    if actual != expected {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+            format!("Optimised hash {:?} differs from reference hash {:?}", actual, expected)));
+    }
+
+    // Hide the inputs and results from the optimiser, so no run can be skipped
+    let start = Instant::now();
+    for _ in 0..runs {
+        black_box(sparse_hash(black_box(params), black_box(&lengths)));
+    }
+    let reference = start.elapsed().as_secs_f64();
+
+    let start = Instant::now();
+    for _ in 0..runs {
+        black_box(fast_sparse_hash(black_box(params), black_box(&lengths)));
+    }
+    let optimised = start.elapsed().as_secs_f64();
+
+    // Each hash processes every length once per round, suffix included, so even empty input has a rate
+    let bytes = (lengths.len() as u64 * params.rounds as u64 * runs as u64) as f64;
+    let runs = runs as f64;
+    Ok(format!("Reference: {:.3} MB/s\nOptimised: {:.3} MB/s\nHashes: {:.0}/s reference, {:.0}/s optimised\nSpeedup: {:.2}x\n",
+        bytes / reference / 1e6, bytes / optimised / 1e6, runs / reference, runs / optimised, reference / optimised))
+}
+
+#[test]
+fn test_day10_bench() {
+    // Empty input still hashes the suffix, so its byte rate is finite and positive
+    let report = bench(&KnotHashParams::default(), b"", 10).unwrap();
+    let rates: Vec<f64> = report.lines()
+        .filter(|line| line.ends_with(" MB/s"))
+        .map(|line| line.split(' ').nth(1).unwrap().parse().unwrap())
+        .collect();
+    assert_eq!(rates.len(), 2, "{}", report);
+    assert!(rates.iter().all(|rate| rate.is_finite() && *rate > 0.0), "{}", report);
+}
+
+// Hash files, or stdin without any, printing like sha256sum
+pub fn hash(args: &mut env::Args) -> Result<(), io::Error> {
+    let mut params = KnotHashParams::default();
+    let mut names = Vec::new();
+
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let number = |value: Option<String>| -> Result<usize, io::Error> {
+        let value = value.unwrap_or_default();
+        value.parse().map_err(|_| invalid(format!("Expected a number, not '{}'", value)))
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--size" => params.size = number(args.next())?,
+            "--rounds" => params.rounds = number(args.next())?,
+            "--block" => params.block = number(args.next())?,
+            "--suffix" => {
+                let value = args.next().unwrap_or_default();
+                params.suffix = value.split(',').filter(|tok| !tok.is_empty())
+                    .map(|tok| tok.parse().map_err(|_| invalid(format!("Expected a byte, not '{}'", tok))))
+                    .collect::<Result<_, _>>()?;
+            },
+            _ => names.push(arg),
+        }
+    }
+
+    if names.is_empty() {
+        names.push("-".to_string());
+    }
+
+    for name in names {
+        let mut input = Vec::new();
+        if name == "-" {
+            io::stdin().read_to_end(&mut input)?;
+        } else {
+            fs::File::open(&name)?.read_to_end(&mut input)?;
+        }
+
+        let mut hasher = KnotHasher::with_params(params.clone());
+        hasher.update(&input);
+        println!("{}  {}", to_hex(&hasher.digest()?), name);
+    }
+
+    Ok(())
+}
+
 pub fn day10(args: &mut env::Args) -> Result<(), io::Error> {
     // Read from file in first arg or default to input.txt
     let input = {
@@ -139,5 +406,10 @@ pub fn day10(args: &mut env::Args) -> Result<(), io::Error> {
     println!("Part 1: {}", part1(256, &input));
     println!("Part 2: {}", knot_hash(&input));
 
+    // Optionally benchmark the knot hash of the input
+    if args.next().as_deref() == Some("bench") {
+        print!("{}", bench(&KnotHashParams::default(), input.trim_end().as_bytes(), 10000)?);
+    }
+
     Ok(())
 }
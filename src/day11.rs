@@ -1,63 +1,267 @@
 use std::fs;
 use std::env;
 use std::io;
-use std::ops;
+use std::iter;
+use std::collections::HashSet;
+use hex::{Direction, HexCoord};
 
-struct HexCoord(isize, isize);
+fn parse(input: &str) -> Vec<Direction> {
+    input
+        // Trim and split tokens
+        .trim_end()
+        .split(',')
+        // Map each token to a direction on the hex grid
+        .map(|tok| match Direction::from_name(tok) {
+            Some(dir) => dir,
+            None => panic!("Invalid token '{}'", tok),
+        })
+        .collect()
+}
 
-impl HexCoord {
-    fn distance(&self) -> usize {
-        let HexCoord(x, y) = self;
-        let (x, y) = (x.abs(), y.abs());
-        // Y must be greater than X to contribute to distance
-        let y = (y - x).max(0) / 2;
-        (x + y) as usize
-    }
+fn names(path: &[Direction]) -> String {
+    path.iter().map(|dir| dir.name()).collect::<Vec<_>>().join(",")
 }
 
-impl ops::Add for HexCoord {
-    type Output = Self;
+fn end(walk: &[Direction]) -> HexCoord {
+    walk.iter().fold(HexCoord::ORIGIN, |pos, &dir| pos.neighbour(dir))
+}
 
-    fn add(self, other: Self) -> Self {
-        HexCoord(self.0 + other.0, self.1 + other.1)
+// Shortest walk with the same end as a walk
+fn simplify(walk: &[Direction]) -> Vec<Direction> {
+    HexCoord::ORIGIN.path_to(end(walk))
+}
+
+#[test]
+fn test_day11_paths() {
+    for &input in &["ne,ne,ne", "ne,ne,sw,sw", "ne,ne,s,s", "se,sw,se,sw,sw", "n,nw,s,sw,sw,se,n,nw,nw"] {
+        let walk = parse(input);
+        let (dist, _, home) = dewit(input);
+        let simple = simplify(&walk);
+        assert_eq!(home.len(), dist);
+        assert_eq!(home.len(), end(&walk).distance());
+        assert_eq!(simple.len(), end(&walk).distance());
+        assert_eq!(end(&simple), end(&walk));
+        assert_eq!(end(&walk) + end(&home), HexCoord::ORIGIN);
     }
+
+    assert_eq!(names(&simplify(&parse("se,sw,se,sw,sw"))), "s,s,sw");
+    assert_eq!(names(&simplify(&parse("n,s,ne,sw"))), "");
+}
+
+// Each position along a walk, starting at the origin
+fn positions<'a>(walk: &'a [Direction]) -> impl Iterator<Item = HexCoord> + 'a {
+    let steps = walk
+        .iter()
+        // Map each direction to a step on the hex grid
+        .map(|&dir| dir.step())
+        // Sum each step to get the new position
+        .scan(HexCoord::ORIGIN, |pos, step| {
+            *pos = *pos + step;
+            Some(*pos)
+        });
+    iter::once(HexCoord::ORIGIN).chain(steps)
 }
 
-fn dewit(input: &str) -> (usize, usize) {
+// Distance at the end and furthest distance, with the shortest way back to the origin
+fn dewit(input: &str) -> (usize, usize, Vec<Direction>) {
     let mut max = 0;
 
-    let dist = input
-        // Trim and split tokens
-        .trim_right()
-        .split(',')
-        // Map each token to a step on the hex grid
-        .map(|tok| match tok {
-            "n" => HexCoord(0, 2),
-            "s" => HexCoord(0, -2),
-            "nw" => HexCoord(-1, 1),
-            "ne" => HexCoord(1, 1),
-            "sw" => HexCoord(-1, -1),
-            "se" => HexCoord(1, -1),
-            _ => panic!(format!("Invalid token '{}'", tok)),
-        })
-        // Sum each step to get the new position
-        .fold(HexCoord(0, 0), |pos, step| {
-            let pos = pos + step;
-            max = max.max(pos.distance());
-            pos
-        })
-        // Return the absolute number of steps from the origin
-        .distance();
+    let end = positions(&parse(input))
+        .inspect(|pos| max = max.max(pos.distance()))
+        .last()
+        .unwrap_or(HexCoord::ORIGIN);
 
-    (dist, max)
+    // Return the absolute number of steps from the origin, and a canonical path back
+    (end.distance(), max, end.path_to(HexCoord::ORIGIN))
 }
 
 #[test]
 fn test_day11() {
-    assert_eq!(dewit("ne,ne,ne"), (3, 3));
-    assert_eq!(dewit("ne,ne,sw,sw"), (0, 2));
-    assert_eq!(dewit("ne,ne,s,s"), (2, 2));
-    assert_eq!(dewit("se,sw,se,sw,sw"), (3, 3));
+    let dewit = |input| {
+        let (dist, max, home) = dewit(input);
+        (dist, max, names(&home))
+    };
+    assert_eq!(dewit("ne,ne,ne"), (3, 3, "sw,sw,sw".to_string()));
+    assert_eq!(dewit("ne,ne,sw,sw"), (0, 2, "".to_string()));
+    assert_eq!(dewit("ne,ne,s,s"), (2, 2, "nw,nw".to_string()));
+    assert_eq!(dewit("se,sw,se,sw,sw"), (3, 3, "n,n,ne".to_string()));
+}
+
+// ==== Rendering ====
+
+// Hex radius and margin of SVG plots, in pixels
+const SVG_HEX: f64 = 10.0;
+const SVG_MARGIN: f64 = 10.0;
+
+// A walk laid out for plotting, with its bounds in doubled coordinates
+struct Plot {
+    trail: Vec<HexCoord>,
+    end: HexCoord,
+    farthest: HexCoord,
+    min: HexCoord,
+    max: HexCoord,
+}
+
+impl Plot {
+    fn new(walk: &[Direction]) -> Plot {
+        let trail: Vec<HexCoord> = positions(walk).collect();
+        let end = trail[trail.len() - 1];
+
+        // The first position furthest from the origin
+        let farthest = trail.iter().fold(HexCoord::ORIGIN, |far, &pos| {
+            if pos.distance() > far.distance() { pos } else { far }
+        });
+
+        let (mut min, mut max) = (HexCoord::ORIGIN, HexCoord::ORIGIN);
+        for &HexCoord(x, y) in &trail {
+            min = HexCoord(min.0.min(x), min.1.min(y));
+            max = HexCoord(max.0.max(x), max.1.max(y));
+        }
+
+        Plot { trail, end, farthest, min, max }
+    }
+
+    fn legend(&self) -> String {
+        format!(
+            "origin (0, 0), end ({}, {}) {} steps away, farthest ({}, {}) {} steps away",
+            self.end.0,
+            self.end.1,
+            self.end.distance(),
+            self.farthest.0,
+            self.farthest.1,
+            self.farthest.distance()
+        )
+    }
+
+    // Hexes as characters two columns apart, with one row per doubled y: O is the origin, E the
+    // end, F the farthest point, # any other visited hex and . a hex that wasn't visited
+    fn to_ascii(&self) -> String {
+        let visited: HashSet<HexCoord> = self.trail.iter().cloned().collect();
+        let mut ascii = String::new();
+
+        for y in (self.min.1..=self.max.1).rev() {
+            let mut line = String::new();
+            for x in self.min.0..=self.max.0 {
+                let pos = HexCoord(x, y);
+                let c = if (x + y) % 2 != 0 {
+                    ' '
+                } else if pos == HexCoord::ORIGIN {
+                    'O'
+                } else if pos == self.end {
+                    'E'
+                } else if pos == self.farthest {
+                    'F'
+                } else if visited.contains(&pos) {
+                    '#'
+                } else {
+                    '.'
+                };
+                line.push(c);
+                line.push(' ');
+            }
+            ascii.push_str(line.trim_end());
+            ascii.push('\n');
+        }
+
+        ascii.push_str(&self.legend());
+        ascii.push('\n');
+        ascii
+    }
+
+    // Pixel centre of a flat-topped hex
+    fn centre(&self, HexCoord(x, y): HexCoord) -> (f64, f64) {
+        let px = (x - self.min.0) as f64 * 1.5 * SVG_HEX + SVG_HEX + SVG_MARGIN;
+        let py = (self.max.1 - y) as f64 * 3f64.sqrt() / 2.0 * SVG_HEX + 3f64.sqrt() / 2.0 * SVG_HEX + SVG_MARGIN;
+        (px, py)
+    }
+
+    // Visited hexes in grey with the walk drawn through them, marking the origin in green, the
+    // end in blue and the farthest point in red
+    fn to_svg(&self) -> String {
+        let width = (self.max.0 - self.min.0) as f64 * 1.5 * SVG_HEX + 2.0 * (SVG_HEX + SVG_MARGIN);
+        let height = (self.max.1 - self.min.1) as f64 * 3f64.sqrt() / 2.0 * SVG_HEX
+            + 3f64.sqrt() * SVG_HEX
+            + 2.0 * SVG_MARGIN;
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{:.0}\" height=\"{:.0}\" font-family=\"monospace\" font-size=\"12\">\n",
+            width,
+            height + 20.0
+        );
+
+        // Each visited hex once, in the order first visited
+        let mut seen = HashSet::new();
+        for &pos in self.trail.iter().filter(|&&pos| seen.insert(pos)) {
+            let (cx, cy) = self.centre(pos);
+            let corners: Vec<String> = (0..6)
+                .map(|i| {
+                    let angle = (60.0 * i as f64).to_radians();
+                    format!("{:.1},{:.1}", cx + SVG_HEX * angle.cos(), cy + SVG_HEX * angle.sin())
+                })
+                .collect();
+            svg.push_str(&format!(
+                "  <polygon points=\"{}\" fill=\"#ddd\" stroke=\"#999\"/>\n",
+                corners.join(" ")
+            ));
+        }
+
+        let points: Vec<String> = self
+            .trail
+            .iter()
+            .map(|&pos| {
+                let (px, py) = self.centre(pos);
+                format!("{:.1},{:.1}", px, py)
+            })
+            .collect();
+        svg.push_str(&format!(
+            "  <polyline points=\"{}\" fill=\"none\" stroke=\"#36c\" stroke-width=\"1.5\"/>\n",
+            points.join(" ")
+        ));
+
+        // Later markers are drawn on top, so the origin stays visible
+        for &(pos, colour, name) in &[(self.farthest, "red", "farthest"), (self.end, "blue", "end"), (HexCoord::ORIGIN, "green", "origin")] {
+            let (cx, cy) = self.centre(pos);
+            svg.push_str(&format!(
+                "  <circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"{:.1}\" fill=\"{}\"><title>{} ({}, {}), {} steps away</title></circle>\n",
+                cx,
+                cy,
+                SVG_HEX / 2.0,
+                colour,
+                name,
+                pos.0,
+                pos.1,
+                pos.distance()
+            ));
+        }
+
+        svg.push_str(&format!(
+            "  <text x=\"{:.0}\" y=\"{:.0}\">{}</text>\n",
+            SVG_MARGIN,
+            height + 10.0,
+            self.legend()
+        ));
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+#[test]
+fn test_day11_render() {
+    let plot = Plot::new(&parse("ne,ne,s,s"));
+    assert_eq!(plot.farthest, HexCoord(2, 2));
+    assert_eq!(
+        plot.to_ascii(),
+        ".   F\n  #\nO   #\n  .\n.   E\norigin (0, 0), end (2, -2) 2 steps away, farthest (2, 2) 2 steps away\n"
+    );
+
+    let svg = plot.to_svg();
+    assert!(svg.starts_with("<svg "));
+    assert_eq!(svg.matches("<polygon ").count(), 5);
+    assert!(svg.contains("<title>origin (0, 0), 0 steps away</title>"));
+    assert!(svg.contains("<title>farthest (2, 2), 2 steps away</title>"));
+    assert!(svg.ends_with("</svg>\n"));
+
+    // The origin wins when a walk goes nowhere
+    assert_eq!(Plot::new(&parse("n,s")).to_ascii().lines().collect::<Vec<_>>()[..3], ["F", "", "O"]);
 }
 
 pub fn day11(args: &mut env::Args) -> Result<(), io::Error> {
@@ -67,8 +271,17 @@ pub fn day11(args: &mut env::Args) -> Result<(), io::Error> {
         fs::read_to_string(name)?
     };
 
-    let (part1, part2) = dewit(&input);
+    let (part1, part2, home) = dewit(&input);
     println!("Part 1: {}\nPart 2: {}", part1, part2);
 
+    // Optionally print the shortest way home, the shortest walk to the same place, or a plot
+    match args.next().as_deref() {
+        Some("home") => println!("{}", names(&home)),
+        Some("simplify") => println!("{}", names(&simplify(&parse(&input)))),
+        Some("ascii") => print!("{}", Plot::new(&parse(&input)).to_ascii()),
+        Some("svg") => print!("{}", Plot::new(&parse(&input)).to_svg()),
+        _ => (),
+    }
+
     Ok(())
 }
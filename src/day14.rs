@@ -13,11 +13,12 @@ fn dewit(input: &str) -> (usize, usize) {
 
     for row in 0..N_ROWS {
         // Compute the knot hash for this row
-        let row_input = format!("{}-{}", input, row);
-        let hash = day10::knot_hash(&row_input);
+        let mut hasher = day10::KnotHasher::new();
+        hasher.update(input.as_bytes());
+        hasher.update(format!("-{}", row).as_bytes());
 
-        // We can easily parse this back into a 128-bit integer
-        let mut hash_bits = u128::from_str_radix(&hash, 16).expect("Unable to parse hash");
+        // The bytes make a 128-bit integer, first byte highest
+        let mut hash_bits = u128::from_be_bytes(hasher.finalize());
 
         // Loop over each set bit from the rhs
         while hash_bits > 0 {
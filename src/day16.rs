@@ -2,6 +2,8 @@ use std::fs;
 use std::env;
 use std::io;
 use std::mem;
+use cycle;
+use cycle::Cycle;
 
 fn apply_pattern(order: &mut Vec<u8>, input: &str) {
     let mut swap = Vec::with_capacity(order.len());
@@ -54,24 +56,23 @@ fn apply_pattern(order: &mut Vec<u8>, input: &str) {
     }
 }
 
-fn generate_order(input: &str, count: u8, iterations: u32) -> String {
-    // Initialize the elements in ascending order
+fn dance_cycle(input: &str, count: u8) -> Cycle {
     let init : Vec<u8> = (0..count).collect();
+    cycle::brent(&init, |order| apply_pattern(order, input))
+}
 
-    // Apply the pattern in a loop
-    let mut order = init.to_vec();
-    for i in 0..iterations {
-        apply_pattern(&mut order, input);
+#[test]
+fn test_day16_dance_cycle() {
+    assert_eq!(dance_cycle("s1,x3/4,pe/b", 5), Cycle { mu: 0, lambda: 4 });
+}
 
-        // If we find a cycle, we can skip to the remainder of the final cycle
-        if &order == &init {
-            println!("Found cycle after {} iterations", i + 1);
-            let remainder = iterations % (i + 1);
-            for _ in 0..remainder {
-                apply_pattern(&mut order, input);
-            }
-            break;
-        }
+fn generate_order(input: &str, count: u8, cycle: &Cycle, iterations: usize) -> String {
+    // Initialize the elements in ascending order
+    let mut order : Vec<u8> = (0..count).collect();
+
+    // The orders repeat, so we can skip to the remainder of the final cycle
+    for _ in 0..cycle.reduce(iterations) {
+        apply_pattern(&mut order, input);
     }
 
     // Map the elements from integers to letters
@@ -80,16 +81,18 @@ fn generate_order(input: &str, count: u8, iterations: u32) -> String {
 
 #[test]
 fn test_day16_part1() {
-    assert_eq!(generate_order("s1,x3/4,pe/b", 5, 1), "baedc");
+    let cycle = dance_cycle("s1,x3/4,pe/b", 5);
+    assert_eq!(generate_order("s1,x3/4,pe/b", 5, &cycle, 1), "baedc");
 }
 
 #[test]
 fn test_day16_part2() {
     // NOTE the example pattern has a cycle after 4 iterations
-    assert_eq!(generate_order("s1,x3/4,pe/b", 5, 2), "ceadb");
-    assert_eq!(generate_order("s1,x3/4,pe/b", 5, 4), "abcde");
-    assert_eq!(generate_order("s1,x3/4,pe/b", 5, 9), "baedc");
-    assert_eq!(generate_order("s1,x3/4,pe/b", 5, 14), "ceadb");
+    let cycle = dance_cycle("s1,x3/4,pe/b", 5);
+    assert_eq!(generate_order("s1,x3/4,pe/b", 5, &cycle, 2), "ceadb");
+    assert_eq!(generate_order("s1,x3/4,pe/b", 5, &cycle, 4), "abcde");
+    assert_eq!(generate_order("s1,x3/4,pe/b", 5, &cycle, 9), "baedc");
+    assert_eq!(generate_order("s1,x3/4,pe/b", 5, &cycle, 14), "ceadb");
 }
 
 pub fn day16(args: &mut env::Args) -> Result<(), io::Error> {
@@ -99,8 +102,11 @@ pub fn day16(args: &mut env::Args) -> Result<(), io::Error> {
         fs::read_to_string(name)?
     };
 
-    println!("Part 1: {}", generate_order(&input, 16, 1));
-    println!("Part 2: {}", generate_order(&input, 16, 1_000_000_000));
+    let cycle = dance_cycle(&input, 16);
+    println!("Dance cycle: tail {}, period {}", cycle.mu, cycle.lambda);
+
+    println!("Part 1: {}", generate_order(&input, 16, &cycle, 1));
+    println!("Part 2: {}", generate_order(&input, 16, &cycle, 1_000_000_000));
 
     Ok(())
 }
@@ -1,6 +1,10 @@
 use std::fs;
 use std::env;
+use std::fmt;
+use std::ops::IndexMut;
+use std::time::Instant;
 use std::io::{Error, ErrorKind};
+use std::collections::{HashMap, BTreeSet};
 use std::sync::mpsc;
 use std::sync::mpsc::{Sender, Receiver, TryRecvError};
 
@@ -17,21 +21,70 @@ pub(crate) enum Op {
     Int(Int),
 }
 
-pub(crate) fn parse_op<'a, I>(tokens: &mut I) -> Result<Op, Error>
+// Register names, where single letters keep their place in the alphabet and longer names follow
+#[derive(Clone, PartialEq, Debug, Default)]
+pub(crate) struct Symbols {
+    names: Vec<String>, // names of registers from LETTERS onwards
+}
+
+impl Symbols {
+    pub(crate) const LETTERS: usize = 26;
+
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    fn intern(&mut self, name: &str) -> Result<Reg, Error> {
+        let index = match self.names.iter().position(|known| known == name) {
+            Some(index) => index,
+            None => {
+                self.names.push(name.to_string());
+                self.names.len() - 1
+            },
+        };
+
+        let reg = Self::LETTERS + index;
+        if reg > Reg::MAX as usize {
+            return Err(Error::new(ErrorKind::InvalidData,
+                format!("Too many registers to name {}", name)));
+        }
+        Ok(reg as Reg)
+    }
+
+    pub(crate) fn name(&self, reg: Reg) -> String {
+        match (reg as usize).checked_sub(Self::LETTERS) {
+            None => ((b'a' + reg) as char).to_string(),
+            Some(index) => match self.names.get(index) {
+                Some(name) => name.clone(),
+                None => format!("${}", reg), // not from this table
+            },
+        }
+    }
+
+    pub(crate) fn op(&self, op: Op) -> String {
+        match op {
+            Op::Reg(reg) => self.name(reg),
+            Op::Int(int) => int.to_string(),
+        }
+    }
+}
+
+pub(crate) fn parse_op<'a, I>(tokens: &mut I, symbols: &mut Symbols) -> Result<Op, Error>
     where I: Iterator<Item = &'a str> {
 
     // Take a token
     let token = tokens.next().ok_or_else(||
         Error::new(ErrorKind::InvalidData, "Expected operand, found EOL"))?;
 
-    // Try and parse a letter (register) first
-    if token.len() == 1 {
-        // (Ab)use from_str_radix to convert letters to numeric
-        if let Ok(base36) = u8::from_str_radix(token, 36) {
-            if base36 >= 10 {
-                return Ok(Op::Reg(base36 - 10));
-            }
-        }
+    // Try and parse a name (register) first
+    let is_name = token.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && token.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if is_name {
+        // (Ab)use from_str_radix to convert single letters to numeric
+        return match u8::from_str_radix(token, 36) {
+            Ok(base36) if token.len() == 1 && base36 >= 10 => Ok(Op::Reg(base36 - 10)),
+            _ => symbols.intern(token).map(Op::Reg),
+        };
     }
 
     // Otherwise, try and parse as an integer
@@ -42,20 +95,158 @@ pub(crate) fn parse_op<'a, I>(tokens: &mut I) -> Result<Op, Error>
     Err(Error::new(ErrorKind::InvalidData, format!("Expected operand, found {}", token)))
 }
 
-pub(crate) fn parse_reg<'a, I>(tokens: &mut I) -> Result<Reg, Error>
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", Symbols::new().op(*self))
+    }
+}
+
+pub(crate) fn parse_reg<'a, I>(tokens: &mut I, symbols: &mut Symbols) -> Result<Reg, Error>
     where I: Iterator<Item = &'a str> {
 
     // Parse an operand, expecting a register
-    match parse_op(tokens)? {
+    match parse_op(tokens, symbols)? {
         Op::Reg(reg) => Ok(reg),
         Op::Int(int) => Err(Error::new(ErrorKind::InvalidData,
             format!("Expected register, found {}", int))),
     }
 }
 
+#[test]
+fn test_day18_parse_op() {
+    let mut symbols = Symbols::new();
+    let mut tokens = "a p count -7 total_2 count 2x".split_whitespace();
+    assert_eq!(parse_op(&mut tokens, &mut symbols).unwrap(), Op::Reg(0));
+    assert_eq!(parse_op(&mut tokens, &mut symbols).unwrap(), Op::Reg(15));
+    assert_eq!(parse_op(&mut tokens, &mut symbols).unwrap(), Op::Reg(26));
+    assert_eq!(parse_op(&mut tokens, &mut symbols).unwrap(), Op::Int(-7));
+    assert_eq!(parse_reg(&mut tokens, &mut symbols).unwrap(), 27);
+    assert_eq!(parse_reg(&mut tokens, &mut symbols).unwrap(), 26);
+    assert!(parse_op(&mut tokens, &mut symbols).is_err());
+    assert_eq!(symbols.name(27), "total_2");
+    assert_eq!(symbols.op(Op::Reg(26)), "count");
+}
+
+// ==== Labels ====
+
+pub(crate) type Labels<'a> = HashMap<&'a str, usize>;
+
+// Strip label definitions (like "loop:") from the input, returning the instruction lines
+pub(crate) fn parse_labels<'a>(input: &'a str) -> Result<(Labels<'a>, Vec<&'a str>), Error> {
+    let mut labels = Labels::new();
+    let mut lines = Vec::new();
+
+    for line in input.trim().lines() {
+        let mut line = line.trim();
+        let mut labeled = false;
+
+        // Take any number of labels from the start of the line
+        while let Some(end) = line.find(':') {
+            let label = &line[..end];
+            if label.is_empty() || label.contains(char::is_whitespace) {
+                break;
+            }
+            if labels.insert(label, lines.len()).is_some() {
+                return Err(Error::new(ErrorKind::InvalidData,
+                    format!("Label {} is defined more than once", label)));
+            }
+            line = line[end + 1..].trim_start();
+            labeled = true;
+        }
+
+        // Labels may stand alone, but other lines must hold an instruction
+        if line.is_empty() && labeled {
+            continue;
+        }
+        lines.push(line);
+    }
+
+    Ok((labels, lines))
+}
+
+// Parse a jump offset, resolving labels relative to pc before trying registers
+pub(crate) fn parse_target<'a, I>(tokens: &mut I, labels: &Labels, symbols: &mut Symbols, pc: usize)
+    -> Result<Op, Error> where I: Iterator<Item = &'a str> {
+
+    match tokens.next() {
+        Some(token) => match labels.get(token) {
+            Some(&target) => Ok(Op::Int(target as Int - pc as Int)),
+            None => parse_op(&mut Some(token).into_iter(), symbols),
+        },
+        None => parse_op(tokens, symbols),
+    }
+}
+
+#[test]
+fn test_day18_parse_labels() {
+    let input = "\
+        start: set a 3\n\
+        loop:\n\
+        add a -1\n\
+        jgz a loop\n\
+        jgz 1 start";
+    let (labels, lines) = parse_labels(input).unwrap();
+    assert_eq!(labels.get("start"), Some(&0));
+    assert_eq!(labels.get("loop"), Some(&1));
+    assert_eq!(lines, vec!["set a 3", "add a -1", "jgz a loop", "jgz 1 start"]);
+
+    let symbols = &mut Symbols::new();
+    let mut tokens = "loop start a".split_whitespace();
+    assert_eq!(parse_target(&mut tokens, &labels, symbols, 2).unwrap(), Op::Int(-1));
+    assert_eq!(parse_target(&mut tokens, &labels, symbols, 3).unwrap(), Op::Int(-3));
+    assert_eq!(parse_target(&mut tokens, &labels, symbols, 3).unwrap(), Op::Reg(0));
+    assert!(parse_target(&mut tokens, &labels, symbols, 3).is_err());
+
+    assert!(parse_labels("a: set a 1\na: set a 2").is_err());
+}
+
 // ==== Instructions and programs ====
 
-#[derive(Copy, Clone, PartialEq)]
+// Common interface to the Duet instruction sets, for tools which work on either
+pub(crate) trait Instruction: Copy + fmt::Display {
+    // Write the instruction as source, naming registers from the symbol table
+    fn fmt_named(&self, f: &mut fmt::Formatter, symbols: &Symbols) -> fmt::Result;
+
+    // The condition and offset operands, if this is a jump
+    fn jump(&self) -> Option<(Op, Op)>;
+
+    // Whether a jump is taken given the value of its condition
+    fn is_taken(&self, cond: Int) -> bool;
+
+    // The registers read and written by this instruction
+    fn reads(&self) -> Vec<Reg>;
+    fn writes(&self) -> Option<Reg>;
+}
+
+// Display an instruction with its register names
+pub(crate) struct Named<'a, I: 'a>(pub(crate) &'a I, pub(crate) &'a Symbols);
+
+impl<'a, I: Instruction> fmt::Display for Named<'a, I> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt_named(f, self.1)
+    }
+}
+
+// Number of register indices a program uses, for sizing its register file
+pub(crate) fn reg_count<I: Instruction>(program: &[I]) -> usize {
+    program.iter()
+        .flat_map(|inst| inst.reads().into_iter().chain(inst.writes()))
+        .map(|reg| reg as usize + 1)
+        .max()
+        .unwrap_or(0)
+}
+
+// Collect the registers from a list of operands
+pub(crate) fn op_regs(ops: &[Op]) -> Vec<Reg> {
+    ops.iter()
+        .filter_map(|op| match *op {
+            Op::Reg(reg) => Some(reg),
+            Op::Int(_) => None,
+        })
+        .collect()
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
 enum Inst {
     SND(Op),
     SET(Reg, Op),
@@ -66,14 +257,72 @@ enum Inst {
     JGZ(Op, Op),
 }
 
+impl Instruction for Inst {
+    fn fmt_named(&self, f: &mut fmt::Formatter, symbols: &Symbols) -> fmt::Result {
+        let reg = |reg| symbols.name(reg);
+        let op = |op| symbols.op(op);
+        match *self {
+            Inst::SND(op1) => write!(f, "snd {}", op(op1)),
+            Inst::SET(r, op1) => write!(f, "set {} {}", reg(r), op(op1)),
+            Inst::ADD(r, op1) => write!(f, "add {} {}", reg(r), op(op1)),
+            Inst::MUL(r, op1) => write!(f, "mul {} {}", reg(r), op(op1)),
+            Inst::MOD(r, op1) => write!(f, "mod {} {}", reg(r), op(op1)),
+            Inst::RCV(r) => write!(f, "rcv {}", reg(r)),
+            Inst::JGZ(op1, op2) => write!(f, "jgz {} {}", op(op1), op(op2)),
+        }
+    }
+
+    fn jump(&self) -> Option<(Op, Op)> {
+        match *self {
+            Inst::JGZ(op1, op2) => Some((op1, op2)),
+            _ => None,
+        }
+    }
+
+    fn is_taken(&self, cond: Int) -> bool {
+        cond > 0
+    }
+
+    fn reads(&self) -> Vec<Reg> {
+        match *self {
+            Inst::SND(op) => op_regs(&[op]),
+            Inst::SET(_, op) => op_regs(&[op]),
+            Inst::ADD(reg, op) | Inst::MUL(reg, op) | Inst::MOD(reg, op) => op_regs(&[Op::Reg(reg), op]),
+            Inst::RCV(_) => Vec::new(),
+            Inst::JGZ(op1, op2) => op_regs(&[op1, op2]),
+        }
+    }
+
+    fn writes(&self) -> Option<Reg> {
+        match *self {
+            Inst::SET(reg, _) | Inst::ADD(reg, _) | Inst::MUL(reg, _) | Inst::MOD(reg, _) | Inst::RCV(reg) => Some(reg),
+            Inst::SND(_) | Inst::JGZ(_, _) => None,
+        }
+    }
+}
+
+impl fmt::Display for Inst {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_named(f, &Symbols::new())
+    }
+}
+
 type Program = Vec<Inst>;
 
+#[cfg(test)]
 fn parse_program(input: &str) -> Result<Program, Error> {
+    parse_named_program(input).map(|(program, _)| program)
+}
+
+// Parse a program along with the names of its registers
+fn parse_named_program(input: &str) -> Result<(Program, Symbols), Error> {
     let mut program = Program::new();
+    let mut symbols = Symbols::new();
+    let (labels, lines) = parse_labels(input)?;
 
     // Parse instructions line-by-line
-    for line in input.trim().lines() {
-        let mut tokens = line.trim().split_whitespace();
+    for (pc, line) in lines.into_iter().enumerate() {
+        let mut tokens = line.split_whitespace();
 
         // Read the instruction name
         let inst = tokens.next().ok_or_else(||
@@ -81,49 +330,701 @@ fn parse_program(input: &str) -> Result<Program, Error> {
 
         // Parse the instruction and expected operands
         program.push(match inst {
-            "snd" => Inst::SND(parse_op(&mut tokens)?),
-            "set" => Inst::SET(parse_reg(&mut tokens)?, parse_op(&mut tokens)?),
-            "add" => Inst::ADD(parse_reg(&mut tokens)?, parse_op(&mut tokens)?),
-            "mul" => Inst::MUL(parse_reg(&mut tokens)?, parse_op(&mut tokens)?),
-            "mod" => Inst::MOD(parse_reg(&mut tokens)?, parse_op(&mut tokens)?),
-            "rcv" => Inst::RCV(parse_reg(&mut tokens)?),
-            "jgz" => Inst::JGZ(parse_op(&mut tokens)?, parse_op(&mut tokens)?),
+            "snd" => Inst::SND(parse_op(&mut tokens, &mut symbols)?),
+            "set" => Inst::SET(parse_reg(&mut tokens, &mut symbols)?, parse_op(&mut tokens, &mut symbols)?),
+            "add" => Inst::ADD(parse_reg(&mut tokens, &mut symbols)?, parse_op(&mut tokens, &mut symbols)?),
+            "mul" => Inst::MUL(parse_reg(&mut tokens, &mut symbols)?, parse_op(&mut tokens, &mut symbols)?),
+            "mod" => Inst::MOD(parse_reg(&mut tokens, &mut symbols)?, parse_op(&mut tokens, &mut symbols)?),
+            "rcv" => Inst::RCV(parse_reg(&mut tokens, &mut symbols)?),
+            "jgz" => Inst::JGZ(parse_op(&mut tokens, &mut symbols)?, parse_target(&mut tokens, &labels, &mut symbols, pc)?),
             _ => return Err(Error::new(ErrorKind::InvalidData,
                 format!("Expected instruction, found {}", inst))),
         })
     }
 
-    Ok(program)
+    Ok((program, symbols))
+}
+
+// ==== Profiling ====
+
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) struct Profile {
+    pub(crate) runs: Vec<u64>,
+    pub(crate) jumps: Vec<u64>,
+}
+
+impl Profile {
+    pub(crate) fn with_len(len: usize) -> Self {
+        Self { runs: vec![0; len], jumps: vec![0; len] }
+    }
+
+    pub(crate) fn record(&mut self, pc: Int, jumped: bool) {
+        let pc = pc as usize;
+        self.runs[pc] += 1;
+        if jumped {
+            self.jumps[pc] += 1;
+        }
+    }
+
+    pub(crate) fn merge(&mut self, other: &Profile) {
+        for (run, other) in self.runs.iter_mut().zip(&other.runs) {
+            *run += other;
+        }
+        for (jump, other) in self.jumps.iter_mut().zip(&other.jumps) {
+            *jump += other;
+        }
+    }
+
+    // Print counts next to each instruction, marking lines within 10% of the hottest
+    pub(crate) fn listing<I: Instruction>(&self, program: &[I], symbols: &Symbols) -> String {
+        let total: u64 = self.runs.iter().sum();
+        let hottest = self.runs.iter().cloned().max().unwrap_or(0);
+
+        let mut listing = format!("{:>4} {:>12} {:>12} {:>7}   source\n", "pc", "runs", "jumps", "%");
+        for (pc, inst) in program.iter().enumerate().take(self.runs.len()) {
+            let runs = self.runs[pc];
+            let percent = if total > 0 { 100.0 * runs as f64 / total as f64 } else { 0.0 };
+            let hot = if runs > 0 && runs * 10 >= hottest * 9 { '*' } else { ' ' };
+            listing.push_str(&format!("{:>4} {:>12} {:>12} {:>6.2}% {} {}\n",
+                pc, runs, self.jumps[pc], percent, hot, Named(inst, symbols)));
+        }
+
+        listing
+    }
+}
+
+#[test]
+fn test_day18_profile_listing() {
+    let mut profile = Profile::with_len(2);
+    profile.record(0, false);
+    profile.record(0, false);
+    for _ in 0..19 {
+        profile.record(1, true);
+    }
+
+    let listing = profile.listing(&parse_program("set a 1\njgz a 0").unwrap(), &Symbols::new());
+    let mut lines = listing.lines().skip(1);
+    // Only lines within 10% of the hottest are marked
+    assert_eq!(lines.next(), Some("   0            2            0   9.52%   set a 1"));
+    assert_eq!(lines.next(), Some("   1           19           19  90.48% * jgz a 0"));
+    assert_eq!(lines.next(), None);
+}
+
+// ==== Disassembly ====
+
+// Write the program back out as canonical source
+pub(crate) fn disassemble<I: Instruction>(program: &[I], symbols: &Symbols) -> String {
+    program.iter().map(|inst| format!("{}\n", Named(inst, symbols))).collect()
+}
+
+// Write the program with addresses, and with constant jump targets resolved
+pub(crate) fn listing<I: Instruction>(program: &[I], symbols: &Symbols) -> String {
+    // Find where each constant jump lands, if it lands anywhere representable
+    let target = |pc: usize| match program[pc].jump() {
+        Some((_, Op::Int(offset))) => Some((pc as Int).checked_add(offset)),
+        _ => None,
+    };
+
+    let mut listing = String::new();
+    for (pc, inst) in program.iter().enumerate() {
+        let mut notes = Vec::new();
+
+        // Describe where this instruction jumps to
+        match (target(pc), inst.jump()) {
+            (Some(Some(to)), _) if to >= 0 && to < program.len() as Int => notes.push(format!("-> {}", to)),
+            (Some(_), _) => notes.push("-> exit".to_string()),
+            (None, Some((_, op))) => notes.push(format!("-> {} + {}", pc, symbols.op(op))),
+            (None, None) => (),
+        }
+
+        // Describe which instructions jump here
+        let sources: Vec<String> = (0..program.len())
+            .filter(|&from| target(from) == Some(Some(pc as Int)))
+            .map(|from| from.to_string())
+            .collect();
+        if !sources.is_empty() {
+            notes.push(format!("<- {}", sources.join(", ")));
+        }
+
+        let line = format!("{:>4}: {}", pc, Named(inst, symbols));
+        if notes.is_empty() {
+            listing.push_str(&format!("{}\n", line));
+        } else {
+            listing.push_str(&format!("{:<24} ; {}\n", line, notes.join("; ")));
+        }
+    }
+
+    listing
+}
+
+#[test]
+fn test_day18_disassemble() {
+    let input = "\
+        set a 1\n\
+        add a 2\n\
+        mul a a\n\
+        mod a 5\n\
+        snd a\n\
+        set a 0\n\
+        rcv a\n\
+        jgz a -1\n\
+        set a 1\n\
+        jgz a -2\n";
+    let program = parse_program(input).unwrap();
+    assert_eq!(disassemble(&program, &Symbols::new()), input);
+    assert_eq!(parse_program(&disassemble(&program, &Symbols::new())).unwrap(), program);
+
+    // Labels assemble to relative offsets
+    let labeled = "\
+        set a 1\n\
+        add a 2\n\
+        mul a a\n\
+        mod a 5\n\
+        snd a\n\
+        set a 0\n\
+        wait: rcv a\n\
+        again: jgz a wait\n\
+        set a 1\n\
+        jgz a again";
+    assert_eq!(parse_program(labeled).unwrap(), program);
+
+    let listing = listing(&parse_program("set a 1\njgz a -1\njgz 1 a\njgz 1 2\njgz 1 9223372036854775807").unwrap(),
+        &Symbols::new());
+    assert_eq!(listing.lines().collect::<Vec<_>>(), vec![
+        "   0: set a 1            ; <- 1",
+        "   1: jgz a -1           ; -> 0",
+        "   2: jgz 1 a            ; -> 2 + a",
+        "   3: jgz 1 2            ; -> exit",
+        "   4: jgz 1 9223372036854775807 ; -> exit",
+    ]);
+
+    // Named registers round trip through the symbol table, and labels shadow them
+    let named = "\
+        set count 3\n\
+        loop: add total count\n\
+        add count -1\n\
+        jgz count loop\n\
+        snd total\n";
+    let (program, symbols) = parse_named_program(named).unwrap();
+    assert_eq!(program[1], Inst::ADD(27, Op::Reg(26)));
+    assert_eq!(program[3], Inst::JGZ(Op::Reg(26), Op::Int(-2)));
+    assert_eq!(disassemble(&program, &symbols), named.replace("loop: ", "").replace("loop", "-2"));
+    assert_eq!(format!("{}", program[1]), "add $27 $26");
+}
+
+// ==== Control-flow analysis ====
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub(crate) enum Target {
+    Block(usize),
+    Exit,
+    Dynamic, // jump offset is a register
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub(crate) struct Edge {
+    pub(crate) to: Target,
+    pub(crate) jump: bool, // false if falling through
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) struct Block {
+    pub(crate) start: usize,
+    pub(crate) end: usize, // exclusive
+    pub(crate) edges: Vec<Edge>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) struct Loop {
+    pub(crate) header: usize, // block index
+    pub(crate) latch: usize, // block index of the back edge
+    pub(crate) body: Vec<usize>, // block indices, including header and latch
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) struct Cfg {
+    pub(crate) blocks: Vec<Block>,
+    pub(crate) loops: Vec<Loop>,
+    pub(crate) reachable: Vec<bool>, // by block; all true if there are dynamic jumps
+    pub(crate) dynamic_jumps: Vec<usize>, // instruction indices
+    pub(crate) uninit_reads: Vec<(usize, Reg)>, // instruction index, register
+}
+
+impl Cfg {
+    pub(crate) fn build<I: Instruction>(program: &[I]) -> Self {
+        let len = program.len();
+        let in_range = |pc: Int| pc >= 0 && pc < len as Int;
+
+        // Split blocks at the entry, at jump targets and after jumps
+        let mut leaders = BTreeSet::new();
+        leaders.insert(0);
+        for (pc, inst) in program.iter().enumerate() {
+            if let Some((_, offset)) = inst.jump() {
+                leaders.insert(pc + 1);
+                if let Op::Int(offset) = offset {
+                    if let Some(to) = (pc as Int).checked_add(offset).filter(|&to| in_range(to)) {
+                        leaders.insert(to as usize);
+                    }
+                }
+            }
+        }
+        let starts: Vec<usize> = leaders.into_iter().filter(|&pc| pc < len).collect();
+        let block_of = |pc: usize| starts.binary_search(&pc).unwrap_or_else(|i| i - 1);
+        let target = |pc: Int| if in_range(pc) { Target::Block(block_of(pc as usize)) } else { Target::Exit };
+
+        // Connect each block by its last instruction
+        let mut blocks = Vec::with_capacity(starts.len());
+        let mut dynamic_jumps = Vec::new();
+        for (i, &start) in starts.iter().enumerate() {
+            let end = starts.get(i + 1).cloned().unwrap_or(len);
+            let last = end - 1;
+            let next = Edge { to: target(end as Int), jump: false };
+
+            let edges = match program[last].jump() {
+                Some((cond, offset)) => {
+                    // Constant conditions either always or never jump
+                    let (taken, falls) = match cond {
+                        Op::Int(cond) => (program[last].is_taken(cond), !program[last].is_taken(cond)),
+                        Op::Reg(_) => (true, true),
+                    };
+
+                    let mut edges = Vec::new();
+                    if taken {
+                        edges.push(Edge { jump: true, to: match offset {
+                            // Jumps past the end of Int leave the program like any other
+                            Op::Int(offset) => (last as Int).checked_add(offset).map_or(Target::Exit, target),
+                            Op::Reg(_) => {
+                                dynamic_jumps.push(last);
+                                Target::Dynamic
+                            },
+                        }});
+                    }
+                    if falls {
+                        edges.push(next);
+                    }
+                    edges
+                },
+                None => vec![next],
+            };
+
+            blocks.push(Block { start, end, edges });
+        }
+
+        let mut cfg = Cfg { blocks, loops: Vec::new(), reachable: Vec::new(), dynamic_jumps, uninit_reads: Vec::new() };
+        cfg.find_reachable();
+        cfg.find_loops();
+        cfg.find_uninit_reads(program);
+        cfg
+    }
+
+    fn successors(&self, block: usize) -> Vec<usize> {
+        self.blocks[block].edges.iter()
+            .filter_map(|edge| match edge.to {
+                Target::Block(to) => Some(to),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn find_reachable(&mut self) {
+        let mut reachable = vec![false; self.blocks.len()];
+        let mut pending = vec![0];
+        while let Some(block) = pending.pop() {
+            if block < reachable.len() && !reachable[block] {
+                reachable[block] = true;
+                pending.extend(self.successors(block));
+            }
+        }
+
+        // A dynamic jump could land anywhere, so we can't rule out any block
+        let dynamic = self.blocks.iter().enumerate()
+            .any(|(i, block)| reachable[i] && block.edges.iter().any(|edge| edge.to == Target::Dynamic));
+        if dynamic {
+            reachable = vec![true; self.blocks.len()];
+        }
+
+        self.reachable = reachable;
+    }
+
+    fn find_loops(&mut self) {
+        // Depth-first search, treating edges to blocks on the stack as back edges
+        let mut back_edges = Vec::new();
+        let mut visited = vec![false; self.blocks.len()];
+        let mut on_stack = vec![false; self.blocks.len()];
+        let mut stack = Vec::new();
+
+        if !self.blocks.is_empty() {
+            visited[0] = true;
+            on_stack[0] = true;
+            stack.push((0, self.successors(0).into_iter()));
+        }
+
+        while !stack.is_empty() {
+            let next = stack.last_mut().unwrap().1.next();
+            let block = stack.last().unwrap().0;
+            match next {
+                Some(to) if on_stack[to] => back_edges.push((to, block)),
+                Some(to) if !visited[to] => {
+                    visited[to] = true;
+                    on_stack[to] = true;
+                    stack.push((to, self.successors(to).into_iter()));
+                },
+                Some(_) => (),
+                None => {
+                    on_stack[block] = false;
+                    stack.pop();
+                },
+            }
+        }
+
+        // The body of each loop is whatever reaches the latch without passing the header
+        let mut preds = vec![Vec::new(); self.blocks.len()];
+        for block in 0..self.blocks.len() {
+            for to in self.successors(block) {
+                preds[to].push(block);
+            }
+        }
+
+        back_edges.sort();
+        self.loops = back_edges.into_iter()
+            .map(|(header, latch)| {
+                let mut body = BTreeSet::new();
+                body.insert(header);
+                let mut pending = vec![latch];
+                while let Some(block) = pending.pop() {
+                    if body.insert(block) {
+                        pending.extend(&preds[block]);
+                    }
+                }
+                Loop { header, latch, body: body.into_iter().collect() }
+            })
+            .collect();
+    }
+
+    fn find_uninit_reads<I: Instruction>(&mut self, program: &[I]) {
+        let count = self.blocks.len();
+        let all: BTreeSet<Reg> = program.iter().flat_map(|inst| inst.reads()).collect();
+
+        // Registers written on every path into each block, starting from everything but the entry
+        let mut written_in = vec![all.clone(); count];
+        if count > 0 {
+            written_in[0].clear();
+        }
+
+        let written_out = |written_in: &[BTreeSet<Reg>], block: usize| {
+            let mut written = written_in[block].clone();
+            written.extend(program[self.blocks[block].start..self.blocks[block].end].iter()
+                .filter_map(|inst| inst.writes()));
+            written
+        };
+
+        // Iterate until the intersection over predecessors stops shrinking
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for block in 0..count {
+                let out = written_out(&written_in, block);
+                let dynamic = self.blocks[block].edges.iter().any(|edge| edge.to == Target::Dynamic);
+                let succs = if dynamic { (0..count).collect() } else { self.successors(block) };
+                for to in succs {
+                    let meet: BTreeSet<Reg> = written_in[to].intersection(&out).cloned().collect();
+                    if meet != written_in[to] {
+                        written_in[to] = meet;
+                        changed = true;
+                    }
+                }
+            }
+        }
+
+        // Walk each reachable block, flagging reads of registers which may not have been written
+        let mut uninit_reads = Vec::new();
+        for (i, block) in self.blocks.iter().enumerate().filter(|&(i, _)| self.reachable[i]) {
+            let mut written = written_in[i].clone();
+            for (pc, inst) in (block.start..block.end).zip(&program[block.start..block.end]) {
+                for reg in inst.reads() {
+                    if !written.contains(&reg) {
+                        uninit_reads.push((pc, reg));
+                    }
+                }
+                written.extend(inst.writes());
+            }
+        }
+
+        uninit_reads.dedup();
+        self.uninit_reads = uninit_reads;
+    }
+
+    // Instruction indices which are never reached from the entry
+    pub(crate) fn unreachable(&self) -> Vec<usize> {
+        self.blocks.iter().enumerate()
+            .filter(|&(i, _)| !self.reachable[i])
+            .flat_map(|(_, block)| block.start..block.end)
+            .collect()
+    }
+
+    pub(crate) fn report(&self, symbols: &Symbols) -> String {
+        let range = |block: usize| format!("{}..{}", self.blocks[block].start, self.blocks[block].end - 1);
+        let mut report = format!("Blocks: {}\n", self.blocks.len());
+
+        for l in &self.loops {
+            let body: Vec<String> = l.body.iter().map(|&block| range(block)).collect();
+            report.push_str(&format!("Loop: header {}, back edge from {}, body {}\n",
+                self.blocks[l.header].start, self.blocks[l.latch].end - 1, body.join(", ")));
+        }
+
+        let unreachable = self.unreachable();
+        if !unreachable.is_empty() {
+            let pcs: Vec<String> = unreachable.iter().map(|pc| pc.to_string()).collect();
+            report.push_str(&format!("Unreachable: {}\n", pcs.join(", ")));
+        }
+
+        for &(pc, reg) in &self.uninit_reads {
+            report.push_str(&format!("Read before write: {} at {}\n", symbols.name(reg), pc));
+        }
+
+        for pc in &self.dynamic_jumps {
+            report.push_str(&format!("Dynamic jump: {}\n", pc));
+        }
+
+        report
+    }
+
+    // Export as a Graphviz digraph, with loops in bold and unreachable blocks greyed out
+    pub(crate) fn to_dot<I: Instruction>(&self, program: &[I], symbols: &Symbols) -> String {
+        let mut dot = String::from("digraph program {\n    node [shape=box, fontname=monospace];\n");
+
+        for (i, block) in self.blocks.iter().enumerate() {
+            let label: String = (block.start..block.end)
+                .map(|pc| format!("{}: {}\\l", pc, Named(&program[pc], symbols)))
+                .collect();
+            let style = if self.reachable[i] { "" } else { ", style=dashed, fontcolor=grey" };
+            dot.push_str(&format!("    b{} [label=\"{}\"{}];\n", block.start, label, style));
+        }
+
+        let mut exit = false;
+        let mut dynamic = false;
+        for (i, block) in self.blocks.iter().enumerate() {
+            for edge in &block.edges {
+                let to = match edge.to {
+                    Target::Block(to) => format!("b{}", self.blocks[to].start),
+                    Target::Exit => { exit = true; "exit".to_string() },
+                    Target::Dynamic => { dynamic = true; "dynamic".to_string() },
+                };
+                let back = match edge.to {
+                    Target::Block(to) => self.loops.iter().any(|l| l.header == to && l.latch == i),
+                    _ => false,
+                };
+                let label = if edge.jump { "jump" } else { "next" };
+                let style = if back { ", style=bold" } else if edge.to == Target::Dynamic { ", style=dashed" } else { "" };
+                dot.push_str(&format!("    b{} -> {} [label={}{}];\n", block.start, to, label, style));
+            }
+        }
+
+        if exit {
+            dot.push_str("    exit [shape=doublecircle];\n");
+        }
+        if dynamic {
+            dot.push_str("    dynamic [shape=diamond, label=\"?\"];\n");
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[test]
+fn test_day18_cfg() {
+    let input = "\
+        set a 1\n\
+        add a 2\n\
+        jgz 1 2\n\
+        mod a 5\n\
+        snd b\n\
+        jgz a -1\n\
+        jgz 0 -6\n\
+        jgz 1 a";
+    let program = parse_program(input).unwrap();
+    let cfg = Cfg::build(&program);
+
+    let starts: Vec<usize> = cfg.blocks.iter().map(|block| block.start).collect();
+    assert_eq!(starts, vec![0, 3, 4, 6, 7]);
+    assert_eq!(cfg.blocks[0].edges, vec![Edge { to: Target::Block(2), jump: true }]);
+    assert_eq!(cfg.blocks[3].edges, vec![Edge { to: Target::Block(4), jump: false }]);
+    assert_eq!(cfg.blocks[4].edges, vec![Edge { to: Target::Dynamic, jump: true }]);
+    assert_eq!(cfg.loops, vec![Loop { header: 2, latch: 2, body: vec![2] }]);
+    assert_eq!(cfg.dynamic_jumps, vec![7]);
+    assert_eq!(cfg.uninit_reads, vec![(4, 1)]);
+
+    // Without the dynamic jump, we can tell that the mod is unreachable
+    let program = parse_program(&input.replace("jgz 1 a", "jgz 1 1")).unwrap();
+    let cfg = Cfg::build(&program);
+    assert_eq!(cfg.unreachable(), vec![3]);
+    assert!(cfg.to_dot(&program, &Symbols::new()).contains("    b3 [label=\"3: mod a 5\\l\", style=dashed, fontcolor=grey];"));
+    assert!(cfg.to_dot(&program, &Symbols::new()).contains("    b4 -> b4 [label=jump, style=bold];"));
+
+    // Jumps too far to represent leave the program
+    let program = parse_program("jgz 1 9223372036854775807\nsnd a").unwrap();
+    let cfg = Cfg::build(&program);
+    assert_eq!(cfg.blocks[0].edges, vec![Edge { to: Target::Exit, jump: true }]);
+    assert_eq!(cfg.unreachable(), vec![1]);
+}
+
+// ==== Arithmetic ====
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub(crate) enum Arith {
+    Wrapping,
+    Checked,
+    Saturating,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub(crate) enum FaultKind {
+    Overflow,
+    DivideByZero,
+}
+
+impl Arith {
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "wrapping" => Some(Arith::Wrapping),
+            "checked" => Some(Arith::Checked),
+            "saturating" => Some(Arith::Saturating),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn add(self, a: Int, b: Int) -> Result<Int, FaultKind> {
+        match self {
+            Arith::Wrapping => Ok(a.wrapping_add(b)),
+            Arith::Checked => a.checked_add(b).ok_or(FaultKind::Overflow),
+            Arith::Saturating => Ok(a.saturating_add(b)),
+        }
+    }
+
+    pub(crate) fn sub(self, a: Int, b: Int) -> Result<Int, FaultKind> {
+        match self {
+            Arith::Wrapping => Ok(a.wrapping_sub(b)),
+            Arith::Checked => a.checked_sub(b).ok_or(FaultKind::Overflow),
+            Arith::Saturating => Ok(a.saturating_sub(b)),
+        }
+    }
+
+    pub(crate) fn mul(self, a: Int, b: Int) -> Result<Int, FaultKind> {
+        match self {
+            Arith::Wrapping => Ok(a.wrapping_mul(b)),
+            Arith::Checked => a.checked_mul(b).ok_or(FaultKind::Overflow),
+            Arith::Saturating => Ok(a.saturating_mul(b)),
+        }
+    }
+
+    // Division by zero faults in every mode, but the remainder itself can't overflow
+    pub(crate) fn rem(self, a: Int, b: Int) -> Result<Int, FaultKind> {
+        match b {
+            0 => Err(FaultKind::DivideByZero),
+            _ => Ok(a.wrapping_rem(b)),
+        }
+    }
+}
+
+#[test]
+fn test_day18_arith() {
+    let max = Int::MAX;
+    assert_eq!(Arith::Wrapping.add(max, 1), Ok(Int::MIN));
+    assert_eq!(Arith::Checked.add(max, 1), Err(FaultKind::Overflow));
+    assert_eq!(Arith::Saturating.add(max, 1), Ok(max));
+    assert_eq!(Arith::Wrapping.sub(-max, 2), Ok(max));
+    assert_eq!(Arith::Checked.sub(-max, 2), Err(FaultKind::Overflow));
+    assert_eq!(Arith::Saturating.sub(-max, 2), Ok(Int::MIN));
+    assert_eq!(Arith::Wrapping.mul(max, 2), Ok(-2));
+    assert_eq!(Arith::Checked.mul(max, 2), Err(FaultKind::Overflow));
+    assert_eq!(Arith::Saturating.mul(max, -2), Ok(Int::MIN));
+    assert_eq!(Arith::Checked.rem(Int::MIN, -1), Ok(0));
+    assert_eq!(Arith::Saturating.rem(7, 0), Err(FaultKind::DivideByZero));
+}
+
+// An arithmetic fault at the given instruction
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub(crate) struct Fault<I> {
+    pub(crate) pc: Int,
+    pub(crate) inst: I,
+    pub(crate) kind: FaultKind,
+}
+
+impl<I: fmt::Display> fmt::Display for Fault<I> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            FaultKind::Overflow => "Arithmetic overflow",
+            FaultKind::DivideByZero => "Division by zero",
+        };
+        write!(f, "{} at instruction {} ({})", kind, self.pc, self.inst)
+    }
+}
+
+impl<I: fmt::Display + fmt::Debug> ::std::error::Error for Fault<I> {}
+
+impl<I: fmt::Display + fmt::Debug + Send + Sync + 'static> From<Fault<I>> for Error {
+    fn from(fault: Fault<I>) -> Self {
+        Error::new(ErrorKind::InvalidData, fault)
+    }
 }
 
 // ==== Virtual machines ====
 
+// Register storage, so programs using only single letters can keep a fixed array
+pub(crate) trait Registers: IndexMut<usize, Output = Int> + AsRef<[Int]> + Clone + PartialEq + fmt::Debug {
+    fn zeroed() -> Self;
+}
+
+impl<const N: usize> Registers for [Int; N] {
+    fn zeroed() -> Self {
+        [0; N]
+    }
+}
+
+impl Registers for Vec<Int> {
+    // Room for every register a symbol table can name
+    fn zeroed() -> Self {
+        vec![0; Reg::MAX as usize + 1]
+    }
+}
+
 enum Message {
     Value(Int),
     Blocked(u32), // received count
     Terminated,
 }
 
-struct Machine {
+struct Machine<R = [Int; Symbols::LETTERS]> {
     pc: Int,
-    regs: [Int; 26],
+    regs: R,
     sender: Sender<Message>,
     receiver: Receiver<Message>,
     send_count: u32,
     receive_count: u32,
+    profile: Option<Profile>,
+    arith: Arith,
 }
 
-impl Machine {
+impl<R: Registers> Machine<R> {
     const P: usize = ('p' as u8 - 'a' as u8) as usize;
 
     fn with_mpsc(sender: Sender<Message>, receiver: Receiver<Message>) -> Self {
         Self {
             pc: 0,
-            regs: [0; 26],
+            regs: R::zeroed(),
             sender,
             receiver,
             send_count: 0,
             receive_count: 0,
+            profile: None,
+            arith: Arith::Checked,
+        }
+    }
+
+    fn record(&mut self, jumped: bool) {
+        if let Some(ref mut profile) = self.profile {
+            profile.record(self.pc, jumped);
         }
     }
 
@@ -138,6 +1039,13 @@ impl Machine {
         &mut self.regs[reg as usize]
     }
 
+    fn update<F>(&mut self, reg: usize, value: Int, op: F) -> Result<(), FaultKind>
+        where F: Fn(Arith, Int, Int) -> Result<Int, FaultKind> {
+
+        self.regs[reg] = op(self.arith, self.regs[reg], value)?;
+        Ok(())
+    }
+
     fn send(&mut self, op: Op) {
         self.send_count += 1;
         // See this link for why a temp is required here; this may be fixed in the future!
@@ -146,42 +1054,59 @@ impl Machine {
         let _ = self.sender.send(Message::Value(value));
     }
 
-    fn is_running(&mut self, program: &Program) -> bool {
-        let len = program.len() as Int;
-        self.pc >= 0 && self.pc < len
+    fn is_running(&self, len: usize) -> bool {
+        self.pc >= 0 && self.pc < len as Int
     }
 
-    fn run_yielding(&mut self, program: &Program) -> Option<Reg> {
-        while self.is_running(&program) {
+    fn run_yielding(&mut self, program: &Program) -> Result<Option<Reg>, Fault<Inst>> {
+        while self.is_running(program.len()) {
             let inst = program[self.pc as usize];
 
             // Handle jump first
             if let Inst::JGZ(op1, op2) = inst {
                 if self.read(op1) > 0 {
-                    self.pc += self.read(op2);
+                    self.record(true);
+                    match self.arith.add(self.pc, self.read(op2)) {
+                        Ok(pc) => self.pc = pc,
+                        Err(kind) => return Err(Fault { pc: self.pc, inst, kind }),
+                    }
                     continue;
                 }
             }
 
             // Otherwise, increment PC by 1
+            self.record(false);
             self.pc += 1;
 
             // Then handle remaining instructions
+            let mut result = Ok(());
             match inst {
                 Inst::SND(op) => self.send(op),
                 Inst::SET(reg, op) => *self.rw(reg) = self.read(op),
-                Inst::ADD(reg, op) => *self.rw(reg) += self.read(op),
-                Inst::MUL(reg, op) => *self.rw(reg) *= self.read(op),
-                Inst::MOD(reg, op) => *self.rw(reg) %= self.read(op),
-                Inst::RCV(reg) => return Some(reg), // yield
+                Inst::ADD(reg, op) => result = self.update(reg as usize, self.read(op), Arith::add),
+                Inst::MUL(reg, op) => result = self.update(reg as usize, self.read(op), Arith::mul),
+                Inst::MOD(reg, op) => result = self.update(reg as usize, self.read(op), Arith::rem),
+                Inst::RCV(reg) => return Ok(Some(reg)), // yield
                 _ => (),
             }
+
+            // Stop at the faulting instruction
+            if let Err(kind) = result {
+                self.pc -= 1;
+                return Err(Fault { pc: self.pc, inst, kind });
+            }
         }
 
-        None
+        Ok(None)
+    }
+
+    fn run_threaded(&mut self, program: &Program, pid: Int) -> Result<u32, Fault<Inst>> {
+        self.run_threaded_with(program.len(), pid, |machine| machine.run_yielding(program))
     }
 
-    fn run_threaded(&mut self, program: &Program, pid: Int) -> u32 {
+    fn run_threaded_with<F>(&mut self, len: usize, pid: Int, mut run_yielding: F) -> Result<u32, Fault<Inst>>
+        where F: FnMut(&mut Self) -> Result<Option<Reg>, Fault<Inst>> {
+
         // Reset virtual machine
         self.pc = 0;
         self.send_count = 0;
@@ -189,10 +1114,10 @@ impl Machine {
         self.regs[Self::P] = pid;
 
         // Run until the first yield
-        let mut reg = self.run_yielding(&program);
+        let mut result = run_yielding(self);
 
-        // Continue to receive messages until we terminate or deadlock
-        while self.is_running(&program) {
+        // Continue to receive messages until we terminate, deadlock or fault
+        while let (true, Ok(Some(reg))) = (self.is_running(len), result) {
             // Receive a message, notifying our partner if we're blocked
             let message = match self.receiver.try_recv() {
                 Ok(message) => message,
@@ -207,8 +1132,8 @@ impl Machine {
             match message {
                 Message::Value(val) => {
                     self.receive_count += 1;
-                    *self.rw(reg.unwrap()) = val;
-                    reg = self.run_yielding(&program);
+                    *self.rw(reg) = val;
+                    result = run_yielding(self);
                 },
                 Message::Blocked(received) => {
                     // Exit the loop if we're deadlocked
@@ -224,26 +1149,244 @@ impl Machine {
 
         // Notify our partner that we're terminating
         let _ = self.sender.send(Message::Terminated);
-        self.send_count
+        result.map(|_| self.send_count)
     }
 }
 
-// ==== Exercises and tests ====
+// Whether a program fits in the fixed array of letter registers
+fn is_lettered(program: &Program) -> bool {
+    reg_count(program) <= Symbols::LETTERS
+}
+
+// ==== Compiled backend ====
+
+// Instructions with operands decoded and jump targets resolved ahead of time
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Code {
+    SndReg(usize),
+    SndInt(Int),
+    SetReg(usize, usize),
+    SetInt(usize, Int),
+    AddReg(usize, usize),
+    AddInt(usize, Int),
+    MulReg(usize, usize),
+    MulInt(usize, Int),
+    ModReg(usize, usize),
+    ModInt(usize, Int),
+    Rcv(Reg),
+    Jump(Int), // absolute target
+    JgzReg(usize, Int), // condition register, absolute target
+    JumpBy(usize), // offset register
+    JgzRegBy(usize, usize), // condition register, offset register
+    JumpFar(Int, Int), // constant condition, offset too far for an absolute target
+    JgzRegFar(usize, Int), // condition register, offset too far for an absolute target
+    Nop,
+}
+
+impl Code {
+    // Recover an arithmetic instruction or relative jump, for reporting faults
+    fn fault_inst(self) -> Inst {
+        match self {
+            Code::AddReg(reg, src) => Inst::ADD(reg as Reg, Op::Reg(src as Reg)),
+            Code::AddInt(reg, int) => Inst::ADD(reg as Reg, Op::Int(int)),
+            Code::MulReg(reg, src) => Inst::MUL(reg as Reg, Op::Reg(src as Reg)),
+            Code::MulInt(reg, int) => Inst::MUL(reg as Reg, Op::Int(int)),
+            Code::ModReg(reg, src) => Inst::MOD(reg as Reg, Op::Reg(src as Reg)),
+            Code::ModInt(reg, int) => Inst::MOD(reg as Reg, Op::Int(int)),
+            Code::JumpBy(offset) => Inst::JGZ(Op::Int(1), Op::Reg(offset as Reg)),
+            Code::JgzRegBy(cond, offset) => Inst::JGZ(Op::Reg(cond as Reg), Op::Reg(offset as Reg)),
+            Code::JumpFar(cond, offset) => Inst::JGZ(Op::Int(cond), Op::Int(offset)),
+            Code::JgzRegFar(cond, offset) => Inst::JGZ(Op::Reg(cond as Reg), Op::Int(offset)),
+            _ => unreachable!("{:?} is not arithmetic", self),
+        }
+    }
+}
 
-fn part1(program: &Program) -> Option<Int> {
+fn compile(program: &Program) -> Vec<Code> {
+    program.iter().enumerate()
+        .map(|(pc, inst)| match *inst {
+            Inst::SND(Op::Reg(src)) => Code::SndReg(src as usize),
+            Inst::SND(Op::Int(int)) => Code::SndInt(int),
+            Inst::SET(reg, Op::Reg(src)) => Code::SetReg(reg as usize, src as usize),
+            Inst::SET(reg, Op::Int(int)) => Code::SetInt(reg as usize, int),
+            Inst::ADD(reg, Op::Reg(src)) => Code::AddReg(reg as usize, src as usize),
+            Inst::ADD(reg, Op::Int(int)) => Code::AddInt(reg as usize, int),
+            Inst::MUL(reg, Op::Reg(src)) => Code::MulReg(reg as usize, src as usize),
+            Inst::MUL(reg, Op::Int(int)) => Code::MulInt(reg as usize, int),
+            Inst::MOD(reg, Op::Reg(src)) => Code::ModReg(reg as usize, src as usize),
+            Inst::MOD(reg, Op::Int(int)) => Code::ModInt(reg as usize, int),
+            Inst::RCV(reg) => Code::Rcv(reg),
+            Inst::JGZ(Op::Int(cond), _) if cond <= 0 => Code::Nop,
+            // Targets past the end of Int depend on the arithmetic mode, so resolve them when taken
+            Inst::JGZ(Op::Int(cond), Op::Int(offset)) => match (pc as Int).checked_add(offset) {
+                Some(target) => Code::Jump(target),
+                None => Code::JumpFar(cond, offset),
+            },
+            Inst::JGZ(Op::Int(_), Op::Reg(offset)) => Code::JumpBy(offset as usize),
+            Inst::JGZ(Op::Reg(cond), Op::Int(offset)) => match (pc as Int).checked_add(offset) {
+                Some(target) => Code::JgzReg(cond as usize, target),
+                None => Code::JgzRegFar(cond as usize, offset),
+            },
+            Inst::JGZ(Op::Reg(cond), Op::Reg(offset)) => Code::JgzRegBy(cond as usize, offset as usize),
+        })
+        .collect()
+}
+
+impl<R: Registers> Machine<R> {
+    // Same as run_yielding, but without profiling
+    fn run_compiled(&mut self, code: &[Code]) -> Result<Option<Reg>, Fault<Inst>> {
+        let mut pc = self.pc;
+
+        // Negative PCs wrap around to large indices, so get() handles both bounds
+        while let Some(&code) = code.get(pc as usize) {
+            pc += 1;
+            let mut result = Ok(());
+            match code {
+                Code::SndReg(src) => {
+                    let value = self.regs[src];
+                    self.send(Op::Int(value));
+                },
+                Code::SndInt(int) => self.send(Op::Int(int)),
+                Code::SetReg(reg, src) => self.regs[reg] = self.regs[src],
+                Code::SetInt(reg, int) => self.regs[reg] = int,
+                Code::AddReg(reg, src) => result = self.update(reg, self.regs[src], Arith::add),
+                Code::AddInt(reg, int) => result = self.update(reg, int, Arith::add),
+                Code::MulReg(reg, src) => result = self.update(reg, self.regs[src], Arith::mul),
+                Code::MulInt(reg, int) => result = self.update(reg, int, Arith::mul),
+                Code::ModReg(reg, src) => result = self.update(reg, self.regs[src], Arith::rem),
+                Code::ModInt(reg, int) => result = self.update(reg, int, Arith::rem),
+                Code::Rcv(reg) => {
+                    self.pc = pc;
+                    return Ok(Some(reg)); // yield
+                },
+                Code::Jump(target) => pc = target,
+                Code::JgzReg(cond, target) => if self.regs[cond] > 0 { pc = target },
+                // Relative jumps are arithmetic on the PC, so they fault like any other
+                Code::JumpBy(offset) => result = self.arith.add(pc - 1, self.regs[offset]).map(|target| pc = target),
+                Code::JgzRegBy(cond, offset) => if self.regs[cond] > 0 {
+                    result = self.arith.add(pc - 1, self.regs[offset]).map(|target| pc = target);
+                },
+                Code::JumpFar(_, offset) => result = self.arith.add(pc - 1, offset).map(|target| pc = target),
+                Code::JgzRegFar(cond, offset) => if self.regs[cond] > 0 {
+                    result = self.arith.add(pc - 1, offset).map(|target| pc = target);
+                },
+                Code::Nop => (),
+            }
+
+            // Stop at the faulting instruction
+            if let Err(kind) = result {
+                self.pc = pc - 1;
+                return Err(Fault { pc: self.pc, inst: code.fault_inst(), kind });
+            }
+        }
+
+        self.pc = pc;
+        Ok(None)
+    }
+
+    fn run_threaded_compiled(&mut self, code: &[Code], pid: Int) -> Result<u32, Fault<Inst>> {
+        self.run_threaded_with(code.len(), pid, |machine| machine.run_compiled(code))
+    }
+}
+
+// Time the interpreter against the compiled backend, failing if their results differ
+pub(crate) fn bench<T, F, G>(runs: u32, mut interpreted: F, mut compiled: G) -> Result<String, Error>
+    where T: PartialEq + fmt::Debug, F: FnMut() -> T, G: FnMut() -> T {
+
+    let expected = interpreted();
+    let actual = compiled();
+    if actual != expected {
+        return Err(Error::new(ErrorKind::InvalidData,
+            format!("Compiled result {:?} differs from interpreted result {:?}", actual, expected)));
+    }
+
+    let start = Instant::now();
+    for _ in 0..runs {
+        interpreted();
+    }
+    let interpreted = start.elapsed().as_secs_f64() / runs as f64;
+
+    let start = Instant::now();
+    for _ in 0..runs {
+        compiled();
+    }
+    let compiled = start.elapsed().as_secs_f64() / runs as f64;
+
+    Ok(format!("Interpreted: {:.3} ms per run\nCompiled: {:.3} ms per run\nSpeedup: {:.2}x\n",
+        interpreted * 1e3, compiled * 1e3, interpreted / compiled))
+}
+
+#[test]
+fn test_day18_compile() {
+    let input = "\
+        set a 1\n\
+        add a 2\n\
+        mul a a\n\
+        mod a 5\n\
+        set b 2\n\
+        jgz b b\n\
+        snd 7\n\
+        snd a\n\
+        set b 3\n\
+        jgz 1 b\n\
+        snd 9\n\
+        snd 9\n\
+        rcv a\n\
+        jgz 0 -20\n\
+        jgz a -7";
+    let program = parse_program(input).unwrap();
+    let code = compile(&program);
+    assert_eq!(code[5], Code::JgzRegBy(1, 1));
+    assert_eq!(code[9], Code::JumpBy(1));
+    assert_eq!(code[13], Code::Nop);
+    assert_eq!(code[14], Code::JgzReg(0, 7));
+
+    // Run both backends up to the first yield, then to the end
     let (tx, rx) = mpsc::channel();
     let (_, dummy) = mpsc::channel();
-    let mut machine = Machine::with_mpsc(tx, dummy);
+    let mut expected = Machine::<[Int; 26]>::with_mpsc(tx.clone(), dummy);
+    let (_, dummy) = mpsc::channel();
+    let mut machine = Machine::<[Int; 26]>::with_mpsc(tx, dummy);
+    for &value in &[4, -3] {
+        assert_eq!(machine.run_compiled(&code), expected.run_yielding(&program));
+        assert_eq!((machine.pc, machine.regs, machine.send_count), (expected.pc, expected.regs, expected.send_count));
+        machine.regs[0] = value;
+        expected.regs[0] = value;
+    }
+    assert_eq!(machine.run_compiled(&code), Ok(None));
+    assert_eq!(expected.run_yielding(&program), Ok(None));
+    assert_eq!((machine.pc, machine.regs), (expected.pc, expected.regs));
 
-    if let None = machine.run_yielding(&program) {
-        return None;
+    // Both machines should have sent the same values
+    let sent: Vec<Int> = rx.try_iter()
+        .map(|message| match message { Message::Value(value) => value, _ => 0 })
+        .collect();
+    assert_eq!(sent, vec![4, 4, 4, 4]);
+}
+
+// ==== Exercises and tests ====
+
+fn part1(program: &Program, arith: Arith) -> Result<Option<Int>, Fault<Inst>> {
+    match is_lettered(program) {
+        true => part1_with::<[Int; 26]>(program, arith),
+        false => part1_with::<Vec<Int>>(program, arith),
     }
+}
+
+fn part1_with<R: Registers>(program: &Program, arith: Arith) -> Result<Option<Int>, Fault<Inst>> {
+    let (tx, rx) = mpsc::channel();
+    let (_, dummy) = mpsc::channel();
+    let mut machine = Machine::<R>::with_mpsc(tx, dummy);
+    machine.arith = arith;
 
-    if let Some(Message::Value(last_snd)) = rx.try_iter().last() {
-        return Some(last_snd);
+    if machine.run_yielding(program)?.is_none() {
+        return Ok(None);
     }
 
-    None
+    match rx.try_iter().last() {
+        Some(Message::Value(last_snd)) => Ok(Some(last_snd)),
+        _ => Ok(None),
+    }
 }
 
 #[test]
@@ -261,19 +1404,66 @@ fn test_day18_part1() {
         jgz a -2";
 
     let program = parse_program(&input).unwrap();
-    assert_eq!(part1(&program), Some(4));
+    assert_eq!(part1(&program, Arith::Checked), Ok(Some(4)));
+}
+
+fn part2(program: &Program, arith: Arith) -> Result<u32, Fault<Inst>> {
+    match is_lettered(program) {
+        true => part2_with::<[Int; 26]>(program, arith),
+        false => part2_with::<Vec<Int>>(program, arith),
+    }
 }
 
-fn part2(program: &Program) -> u32 {
+fn part2_with<R: Registers>(program: &Program, arith: Arith) -> Result<u32, Fault<Inst>> {
     // Open a pair of channels
     let (tx0, rx1) = mpsc::channel();
     let (tx1, rx0) = mpsc::channel();
 
+    let run = move |sender, receiver, pid| {
+        let mut machine = Machine::<R>::with_mpsc(sender, receiver);
+        machine.arith = arith;
+        machine.run_threaded(program, pid)
+    };
+
     // Use scoped threads so we gurantee the lifetime of the program reference
     crossbeam::scope(|scope| {
-        let _handle0 = scope.spawn(move || Machine::with_mpsc(tx0, rx0).run_threaded(program, 0));
-        let handle1 = scope.spawn(move || Machine::with_mpsc(tx1, rx1).run_threaded(program, 1));
-        handle1.join().unwrap()
+        let handle0 = scope.spawn(move || run(tx0, rx0, 0));
+        let handle1 = scope.spawn(move || run(tx1, rx1, 1));
+        let count = handle1.join().unwrap();
+
+        // A fault in either machine takes precedence over the send count
+        handle0.join().unwrap()?;
+        count
+    })
+}
+
+fn part2_compiled(program: &Program, arith: Arith) -> Result<u32, Fault<Inst>> {
+    match is_lettered(program) {
+        true => part2_compiled_with::<[Int; 26]>(program, arith),
+        false => part2_compiled_with::<Vec<Int>>(program, arith),
+    }
+}
+
+fn part2_compiled_with<R: Registers>(program: &Program, arith: Arith) -> Result<u32, Fault<Inst>> {
+    let code = compile(program);
+    let code = &code;
+
+    // Same as part 2, but with each machine running the compiled code
+    let (tx0, rx1) = mpsc::channel();
+    let (tx1, rx0) = mpsc::channel();
+
+    let run = move |sender, receiver, pid| {
+        let mut machine = Machine::<R>::with_mpsc(sender, receiver);
+        machine.arith = arith;
+        machine.run_threaded_compiled(code, pid)
+    };
+
+    crossbeam::scope(|scope| {
+        let handle0 = scope.spawn(move || run(tx0, rx0, 0));
+        let handle1 = scope.spawn(move || run(tx1, rx1, 1));
+        let count = handle1.join().unwrap();
+        handle0.join().unwrap()?;
+        count
     })
 }
 
@@ -289,7 +1479,119 @@ fn test_day18_part2() {
         rcv d";
 
     let program = parse_program(&input).unwrap();
-    assert_eq!(part2(&program), 3);
+    assert_eq!(part2(&program, Arith::Checked), Ok(3));
+    assert_eq!(part2_compiled(&program, Arith::Checked), Ok(3));
+
+    // Named registers run on a register vector
+    let named = input.replace("rcv a", "rcv first").replace("rcv d", "rcv last");
+    let program = parse_program(&named).unwrap();
+    assert!(!is_lettered(&program));
+    assert_eq!(part2(&program, Arith::Checked), Ok(3));
+    assert_eq!(part2_compiled(&program, Arith::Checked), Ok(3));
+}
+
+#[test]
+fn test_day18_fault() {
+    let input = "\
+        set a 2\n\
+        mul a 9223372036854775807\n\
+        snd a\n\
+        rcv a";
+
+    let program = parse_program(input).unwrap();
+    let overflow = Fault { pc: 1, inst: program[1], kind: FaultKind::Overflow };
+    assert_eq!(part1(&program, Arith::Checked), Err(overflow));
+    assert_eq!(part1(&program, Arith::Wrapping), Ok(Some(-2)));
+    assert_eq!(part1(&program, Arith::Saturating), Ok(Some(Int::MAX)));
+    assert_eq!(part2(&program, Arith::Checked), Err(overflow));
+    assert_eq!(part2_compiled(&program, Arith::Checked), Err(overflow));
+    assert_eq!(part2_compiled(&program, Arith::Saturating), Ok(1));
+
+    // Only program 0 divides by zero, while program 1 waits to receive
+    let input = "\
+        mod a p\n\
+        snd a\n\
+        rcv a";
+
+    let program = parse_program(input).unwrap();
+    let zero = Fault { pc: 0, inst: program[0], kind: FaultKind::DivideByZero };
+    assert_eq!(part1(&program, Arith::Wrapping), Err(zero));
+    assert_eq!(part2(&program, Arith::Wrapping), Err(zero));
+    assert_eq!(part2_compiled(&program, Arith::Wrapping), Err(zero));
+
+    // Relative jumps overflow the PC like any other arithmetic
+    let input = "\
+        set b 9223372036854775807\n\
+        jgz 1 b\n\
+        snd b\n\
+        rcv b";
+
+    let program = parse_program(input).unwrap();
+    let overflow = Fault { pc: 1, inst: program[1], kind: FaultKind::Overflow };
+    assert_eq!(part1(&program, Arith::Checked), Err(overflow));
+    assert_eq!(part1(&program, Arith::Wrapping), Ok(None));
+    assert_eq!(part2(&program, Arith::Checked), Err(overflow));
+    assert_eq!(part2_compiled(&program, Arith::Checked), Err(overflow));
+    assert_eq!(part2_compiled(&program, Arith::Saturating), Ok(0));
+
+    // As do constant jumps, whether the condition is constant or not
+    for input in &["set a 1\njgz 1 9223372036854775807\nsnd a\nrcv a", "set a 1\njgz a 9223372036854775807\nsnd a\nrcv a"] {
+        let program = parse_program(input).unwrap();
+        let overflow = Fault { pc: 1, inst: program[1], kind: FaultKind::Overflow };
+        assert_eq!(part1(&program, Arith::Checked), Err(overflow));
+        assert_eq!(part2(&program, Arith::Checked), Err(overflow));
+        assert_eq!(part2_compiled(&program, Arith::Checked), Err(overflow));
+        assert_eq!(part1(&program, Arith::Wrapping), Ok(None));
+        assert_eq!(part2_compiled(&program, Arith::Wrapping), part2(&program, Arith::Wrapping));
+        assert_eq!(part2_compiled(&program, Arith::Saturating), part2(&program, Arith::Saturating));
+    }
+}
+
+fn profile(program: &Program) -> Profile {
+    match is_lettered(program) {
+        true => profile_with::<[Int; 26]>(program),
+        false => profile_with::<Vec<Int>>(program),
+    }
+}
+
+fn profile_with<R: Registers>(program: &Program) -> Profile {
+    let (tx0, rx1) = mpsc::channel();
+    let (tx1, rx0) = mpsc::channel();
+
+    // Same as part 2, but hand back each machine's profile
+    let run = |sender, receiver, pid| {
+        let mut machine = Machine::<R>::with_mpsc(sender, receiver);
+        machine.profile = Some(Profile::with_len(program.len()));
+        machine.arith = Arith::Wrapping; // profile as far as we can get
+        let _ = machine.run_threaded(program, pid);
+        machine.profile.unwrap()
+    };
+
+    let (mut profile0, profile1) = crossbeam::scope(|scope| {
+        let handle0 = scope.spawn(move || run(tx0, rx0, 0));
+        let handle1 = scope.spawn(move || run(tx1, rx1, 1));
+        (handle0.join().unwrap(), handle1.join().unwrap())
+    });
+
+    profile0.merge(&profile1);
+    profile0
+}
+
+#[test]
+fn test_day18_profile() {
+    let input =
+        "snd 1
+        snd 2
+        snd p
+        rcv a
+        rcv b
+        rcv c
+        rcv d";
+
+    let program = parse_program(input).unwrap();
+    let profile = profile(&program);
+    assert_eq!(profile.runs, vec![2; 7]);
+    assert_eq!(profile.jumps, vec![0; 7]);
 }
 
 pub fn day18(args: &mut env::Args) -> Result<(), Error> {
@@ -299,9 +1601,30 @@ pub fn day18(args: &mut env::Args) -> Result<(), Error> {
         fs::read_to_string(name)?
     };
 
-    let program = parse_program(&input)?;
-    println!("Part 1: {}", part1(&program).unwrap());
-    println!("Part 2: {}", part2(&program));
+    // Optionally select an arithmetic mode, defaulting to checked
+    let mut mode = args.next();
+    let arith = match mode.as_deref().and_then(Arith::from_name) {
+        Some(arith) => { mode = args.next(); arith },
+        None => Arith::Checked,
+    };
+
+    let (program, symbols) = parse_named_program(&input)?;
+    match part1(&program, arith)? {
+        Some(value) => println!("Part 1: {}", value),
+        None => println!("Part 1: no value was recovered"),
+    }
+    println!("Part 2: {}", part2(&program, arith)?);
+
+    // Optionally print a hot-spot listing of part 2, or the program itself
+    match mode.as_deref() {
+        Some("profile") => print!("{}", profile(&program).listing(&program, &symbols)),
+        Some("disasm") => print!("{}", disassemble(&program, &symbols)),
+        Some("listing") => print!("{}", listing(&program, &symbols)),
+        Some("cfg") => print!("{}", Cfg::build(&program).report(&symbols)),
+        Some("dot") => print!("{}", Cfg::build(&program).to_dot(&program, &symbols)),
+        Some("bench") => print!("{}", bench(100, || part2(&program, arith), || part2_compiled(&program, arith))?),
+        _ => (),
+    }
 
     Ok(())
 }
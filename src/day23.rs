@@ -1,4 +1,4 @@
-use std::{fs, env};
+use std::{fs, env, fmt};
 use std::io::{Error, ErrorKind};
 
 use super::day18::*;
@@ -9,14 +9,83 @@ enum Inst {
     SUB(Reg, Op),
     MUL(Reg, Op),
     JNZ(Op, Op),
+    OPT(Super),
+}
+
+// Superinstructions which replace the first instruction of a known loop
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Super {
+    // sub reg op; sub count step; jnz count -2
+    MulSub { reg: Reg, op: Op, count: Reg, step: Int },
+    // sub reg step; jnz reg -1
+    Clear { reg: Reg, step: Int },
+    // Loop e from e to n, clearing flag if d * e == n (see COMPOSITE_LOOP)
+    Divides { flag: Reg, d: Reg, e: Reg, n: Reg, tmp: Reg },
+    // Loop d from d to n around Divides, with e starting from init
+    Factors { flag: Reg, d: Reg, e: Reg, n: Reg, tmp: Reg, init: Int },
+}
+
+impl Instruction for Inst {
+    fn fmt_named(&self, f: &mut fmt::Formatter, symbols: &Symbols) -> fmt::Result {
+        let reg = |reg| symbols.name(reg);
+        let op = |op| symbols.op(op);
+        match *self {
+            Inst::SET(r, op1) => write!(f, "set {} {}", reg(r), op(op1)),
+            Inst::SUB(r, op1) => write!(f, "sub {} {}", reg(r), op(op1)),
+            Inst::MUL(r, op1) => write!(f, "mul {} {}", reg(r), op(op1)),
+            Inst::JNZ(op1, op2) => write!(f, "jnz {} {}", op(op1), op(op2)),
+            // Superinstructions behave exactly like the code they replaced
+            Inst::OPT(opt) => opt.fallback().fmt_named(f, symbols),
+        }
+    }
+
+    fn jump(&self) -> Option<(Op, Op)> {
+        match *self {
+            Inst::JNZ(op1, op2) => Some((op1, op2)),
+            Inst::OPT(opt) => opt.fallback().jump(),
+            _ => None,
+        }
+    }
+
+    fn is_taken(&self, cond: Int) -> bool {
+        cond != 0
+    }
+
+    fn reads(&self) -> Vec<Reg> {
+        match *self {
+            Inst::SET(_, op) => op_regs(&[op]),
+            Inst::SUB(reg, op) | Inst::MUL(reg, op) => op_regs(&[Op::Reg(reg), op]),
+            Inst::JNZ(op1, op2) => op_regs(&[op1, op2]),
+            Inst::OPT(opt) => opt.fallback().reads(),
+        }
+    }
+
+    fn writes(&self) -> Option<Reg> {
+        match *self {
+            Inst::SET(reg, _) | Inst::SUB(reg, _) | Inst::MUL(reg, _) => Some(reg),
+            Inst::JNZ(_, _) => None,
+            Inst::OPT(opt) => opt.fallback().writes(),
+        }
+    }
+}
+
+impl fmt::Display for Inst {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_named(f, &Symbols::new())
+    }
 }
 
 type Program = Vec<Inst>;
 
-struct Machine {
+// Registers a to h
+const REG_COUNT: usize = 8;
+
+struct Machine<R = [Int; REG_COUNT]> {
     pc: Int,
-    regs: [Int; Machine::REG_COUNT],
-    mul_count: u32,
+    regs: R,
+    mul_count: u64,
+    profile: Option<Profile>,
+    arith: Arith,
 }
 
 pub fn day23(args: &mut env::Args) -> Result<(), Error> {
@@ -26,33 +95,222 @@ pub fn day23(args: &mut env::Args) -> Result<(), Error> {
         fs::read_to_string(name)?
     };
 
-    println!("Part 1: {}", part1(&input));
-    println!("Part 2: {}", part2());
+    // Optionally select an arithmetic mode, defaulting to checked
+    let mut mode = args.next();
+    let arith = match mode.as_deref().and_then(Arith::from_name) {
+        Some(arith) => { mode = args.next(); arith },
+        None => Arith::Checked,
+    };
+
+    let (program, symbols) = parse_named_program(&input)?;
+    println!("Part 1: {}", part1(&program, arith)?);
+    println!("Part 2: {}", part2(&program)?);
+
+    // Optionally print a hot-spot listing of part 1, run part 2 on the VM, or print the program
+    match mode.as_deref() {
+        Some("profile") => print!("{}", profile(&program).listing(&program, &symbols)),
+        Some("optimize") => println!("Part 2 (optimized): {}", run_optimized(&program, arith)?),
+        Some("disasm") => print!("{}", disassemble(&program, &symbols)),
+        Some("listing") => print!("{}", listing(&program, &symbols)),
+        Some("cfg") => print!("{}", Cfg::build(&program).report(&symbols)),
+        Some("dot") => print!("{}", Cfg::build(&program).to_dot(&program, &symbols)),
+        Some("bench") => print!("{}", bench(100, || run_part1(&program, arith), || run_part1_compiled(&program, arith))?),
+        _ => (),
+    }
 
     Ok(())
 }
 
-fn part1(input: &str) -> u32 {
+fn part1(program: &Program, arith: Arith) -> Result<u64, Fault<Inst>> {
+    run_part1(program, arith).map(|(_, _, mul_count)| mul_count)
+}
+
+#[test]
+fn test_day23_part1() {
+    let input = "\
+        set a 3\n\
+        mul a a\n\
+        mul b a\n\
+        set c -9223372036854775807\n\
+        sub c 2\n\
+        mul a c";
     let program = parse_program(input).unwrap();
-    let mut machine = Machine::new();
-    machine.run(&program);
-    machine.mul_count
+    let overflow = Fault { pc: 4, inst: program[4], kind: FaultKind::Overflow };
+    assert_eq!(part1(&program, Arith::Checked), Err(overflow));
+    assert_eq!(part1(&program, Arith::Wrapping), Ok(3));
+    assert_eq!(run_part1_compiled(&program, Arith::Checked), Err(overflow));
+    assert_eq!(run_part1_compiled(&program, Arith::Saturating), run_part1(&program, Arith::Saturating));
+
+    // A faulting mul isn't counted
+    let program = parse_program("set a 3\nmul a a\nset c 9223372036854775807\nmul c 2").unwrap();
+    for &compiled in &[false, true] {
+        let mut machine = Machine::new();
+        let result = match compiled {
+            true => machine.run_compiled(&compile(&program)),
+            false => machine.run(&program),
+        };
+        assert_eq!(result, Err(Fault { pc: 3, inst: program[3], kind: FaultKind::Overflow }));
+        assert_eq!(machine.mul_count, 1);
+    }
+
+    // Relative jumps overflow the PC like any other arithmetic
+    let program = parse_program("set b 9223372036854775807\njnz 1 b\nmul b b").unwrap();
+    let overflow = Fault { pc: 1, inst: program[1], kind: FaultKind::Overflow };
+    assert_eq!(run_part1(&program, Arith::Checked), Err(overflow));
+    assert_eq!(run_part1_compiled(&program, Arith::Checked), Err(overflow));
+    assert_eq!(part1(&program, Arith::Wrapping), Ok(0));
+    assert_eq!(run_part1_compiled(&program, Arith::Saturating), run_part1(&program, Arith::Saturating));
+
+    // As do constant jumps, whether the condition is constant or not
+    for input in &["set a 1\njnz 1 9223372036854775807\nmul a a", "set a 1\njnz a 9223372036854775807\nmul a a"] {
+        let program = parse_program(input).unwrap();
+        let overflow = Fault { pc: 1, inst: program[1], kind: FaultKind::Overflow };
+        assert_eq!(run_part1(&program, Arith::Checked), Err(overflow));
+        assert_eq!(run_part1_compiled(&program, Arith::Checked), Err(overflow));
+        assert_eq!(run_part1_compiled(&program, Arith::Wrapping), run_part1(&program, Arith::Wrapping));
+        assert_eq!(run_part1_compiled(&program, Arith::Saturating), run_part1(&program, Arith::Saturating));
+    }
 }
 
-fn part2() -> i64 {
-    // Decompile the given assembly into Rust
-    let b = 57 * 100 + 100000;
-    let c = b + 17000;
+fn profile(program: &Program) -> Profile {
+    match Machine::fits(program) {
+        true => profile_with::<[Int; REG_COUNT]>(program),
+        false => profile_with::<Vec<Int>>(program),
+    }
+}
 
-    // Loop from b to c, counting non-primes
-    let mut h = 0;
-    for n in (b..=c).step_by(17) {
-        if !is_prime(n) {
-            h += 1;
+fn profile_with<R: Registers>(program: &Program) -> Profile {
+    let mut machine = Machine::<R>::zeroed();
+    machine.profile = Some(Profile::with_len(program.len()));
+    machine.arith = Arith::Wrapping; // profile as far as we can get
+    let _ = machine.run(program);
+    machine.profile.unwrap()
+}
+
+#[test]
+fn test_day23_profile() {
+    let input = "\
+        set a 3\n\
+        sub a 1\n\
+        jnz a -1\n\
+        mul b 2";
+    let profile = profile(&parse_program(input).unwrap());
+    assert_eq!(profile.runs, vec![1, 3, 3, 1]);
+    assert_eq!(profile.jumps, vec![0, 0, 2, 0]);
+}
+
+// The loop counting composites in b..=c, following a prelude which sets b and c
+const COMPOSITE_LOOP: &str = "\
+    set f 1\n\
+    set d 2\n\
+    set e 2\n\
+    set g d\n\
+    mul g e\n\
+    sub g b\n\
+    jnz g 2\n\
+    set f 0\n\
+    sub e -1\n\
+    set g e\n\
+    sub g b\n\
+    jnz g -8\n\
+    sub d -1\n\
+    set g d\n\
+    sub g b\n\
+    jnz g -13\n\
+    jnz f 2\n\
+    sub h -1\n\
+    set g b\n\
+    sub g c\n\
+    jnz g 2\n\
+    jnz 1 3\n\
+    sub b -17\n\
+    jnz 1 -23";
+
+// Index of the step instruction in COMPOSITE_LOOP, which may differ between inputs
+const COMPOSITE_STEP: usize = 22;
+
+fn part2(program: &Program) -> Result<i64, Error> {
+    let invalid = |message: String| Error::new(ErrorKind::InvalidData, message);
+
+    // Split the program into the prelude and the loop
+    let pattern = parse_program(COMPOSITE_LOOP)?;
+    if program.len() < pattern.len() {
+        return Err(invalid(format!("Expected at least {} instructions, found {}",
+            pattern.len(), program.len())));
+    }
+    let start = program.len() - pattern.len();
+
+    // Match the loop against the pattern, extracting the step
+    let mut step = 0;
+    for (i, (inst, expected)) in program[start..].iter().zip(&pattern).enumerate() {
+        match (i, *inst) {
+            (COMPOSITE_STEP, Inst::SUB(1, Op::Int(int))) => step = int.checked_neg()
+                .ok_or_else(|| invalid(format!("Step {} at instruction {} can't be negated", int, start + i)))?,
+            _ if inst == expected => (),
+            _ => return Err(invalid(format!("Expected {:?} at instruction {}, found {:?}",
+                expected, start + i, inst))),
+        }
+    }
+
+    // The prelude must fall through to the loop without jumping backwards
+    for (i, inst) in program[..start].iter().enumerate() {
+        if let Inst::JNZ(_, offset) = *inst {
+            match offset {
+                Op::Int(int) if int > 0 => (),
+                _ => return Err(invalid(format!("Expected forward jump at instruction {}, found {:?}",
+                    i, inst))),
+            }
         }
     }
 
-    h
+    // Run the prelude with a = 1 to find the seed and range
+    let mut machine = Machine::<Vec<Int>>::zeroed();
+    machine.regs[0] = 1;
+    machine.run_to(program, start as Int)?;
+    if machine.pc != start as Int {
+        return Err(invalid(format!("Expected prelude to exit at instruction {}, found {}",
+            start, machine.pc)));
+    }
+    let (b, c, h) = (machine.regs[1], machine.regs[2], machine.regs[7]);
+
+    // The loop only terminates if stepping from b lands exactly on c
+    if step <= 0 || c < b || (c - b) % step != 0 {
+        return Err(invalid(format!("Loop from {} to {} by {} never terminates", b, c, step)));
+    }
+
+    // Loop from b to c, counting non-primes
+    let composites = (b..=c)
+        .step_by(step as usize)
+        .filter(|&n| !is_prime(n))
+        .count();
+
+    Ok(h + composites as i64)
+}
+
+#[test]
+fn test_day23_part2() {
+    // Use a small range so we can check the result by running the program
+    let input = format!("set b 11\nset c b\njnz a 2\njnz 1 2\nsub c -20\n{}",
+        COMPOSITE_LOOP.replace("sub b -17", "sub b -5"));
+    let program = parse_program(&input).unwrap();
+
+    let mut machine = Machine::new();
+    machine.regs[0] = 1;
+    machine.run(&program).unwrap();
+    assert_eq!(machine.regs[7], 3);
+    assert_eq!(part2(&program).unwrap(), 3);
+
+    // A different step in the loop is fine, but a different shape is not
+    let broken = input.replace("jnz f 2", "jnz e 2");
+    assert!(part2(&parse_program(&broken).unwrap()).is_err());
+
+    // Neither is a range which the step overshoots
+    let overshoot = input.replace("sub b -5", "sub b -3");
+    assert!(part2(&parse_program(&overshoot).unwrap()).is_err());
+
+    // Or a step which overflows when negated
+    let overflow = input.replace("sub b -5", "sub b -9223372036854775808");
+    assert!(part2(&parse_program(&overflow).unwrap()).is_err());
 }
 
 fn is_prime(n: i64) -> bool {
@@ -85,11 +343,18 @@ fn test_day23_is_prime() {
 }
 
 fn parse_program(input: &str) -> Result<Program, Error> {
+    parse_named_program(input).map(|(program, _)| program)
+}
+
+// Parse a program along with the names of its registers
+fn parse_named_program(input: &str) -> Result<(Program, Symbols), Error> {
     let mut program = Program::new();
+    let mut symbols = Symbols::new();
+    let (labels, lines) = parse_labels(input)?;
 
     // Parse instructions line-by-line
-    for line in input.trim().lines() {
-        let mut tokens = line.trim().split_whitespace();
+    for (pc, line) in lines.into_iter().enumerate() {
+        let mut tokens = line.split_whitespace();
 
         // Read the instruction name
         let inst = tokens.next().ok_or_else(||
@@ -97,16 +362,16 @@ fn parse_program(input: &str) -> Result<Program, Error> {
 
         // Parse the instruction and expected operands
         program.push(match inst {
-            "set" => Inst::SET(parse_reg(&mut tokens)?, parse_op(&mut tokens)?),
-            "sub" => Inst::SUB(parse_reg(&mut tokens)?, parse_op(&mut tokens)?),
-            "mul" => Inst::MUL(parse_reg(&mut tokens)?, parse_op(&mut tokens)?),
-            "jnz" => Inst::JNZ(parse_op(&mut tokens)?, parse_op(&mut tokens)?),
+            "set" => Inst::SET(parse_reg(&mut tokens, &mut symbols)?, parse_op(&mut tokens, &mut symbols)?),
+            "sub" => Inst::SUB(parse_reg(&mut tokens, &mut symbols)?, parse_op(&mut tokens, &mut symbols)?),
+            "mul" => Inst::MUL(parse_reg(&mut tokens, &mut symbols)?, parse_op(&mut tokens, &mut symbols)?),
+            "jnz" => Inst::JNZ(parse_op(&mut tokens, &mut symbols)?, parse_target(&mut tokens, &labels, &mut symbols, pc)?),
             _ => return Err(Error::new(ErrorKind::InvalidData,
                 format!("Expected instruction, found {}", inst))),
         })
     }
 
-    Ok(program)
+    Ok((program, symbols))
 }
 
 #[test]
@@ -121,13 +386,270 @@ fn test_day23_parse_program() {
     assert_eq!(program[1], Inst::SUB(1, Op::Int(-2)));
     assert_eq!(program[2], Inst::MUL(2, Op::Int(-3)));
     assert_eq!(program[3], Inst::JNZ(Op::Reg(3), Op::Int(-4)));
+
+    // Named registers run on a register vector
+    let input = "\
+        set total 0\n\
+        set step 3\n\
+        sub total step\n\
+        mul total total\n\
+        set h total";
+    let (program, symbols) = parse_named_program(input).unwrap();
+    assert_eq!(program[2], Inst::SUB(26, Op::Reg(27)));
+    assert_eq!(symbols.name(27), "step");
+    assert_eq!(run_part1(&program, Arith::Checked).unwrap().1[7], 9);
+    assert_eq!(run_part1_compiled(&program, Arith::Checked), run_part1(&program, Arith::Checked));
 }
 
-impl Machine {
-    const REG_COUNT: usize = 8;
+#[test]
+fn test_day23_disassemble() {
+    let input = format!("set b 57\nset c b\njnz a 2\njnz 1 5\nmul b 100\nsub b -100000\n\
+        set c b\nsub c -17000\n{}\n", COMPOSITE_LOOP);
+    let program = parse_program(&input).unwrap();
+    assert_eq!(disassemble(&program, &Symbols::new()), input);
+
+    // Superinstructions disassemble to the code they replaced
+    assert_eq!(disassemble(&optimize(&program), &Symbols::new()), input);
+
+    // Labels assemble to relative offsets
+    let labeled = "\
+        set a 3\n\
+        loop:\n\
+        sub a 1\n\
+        jnz a loop\n\
+        jnz 1 end\n\
+        mul a a\n\
+        end: set b a";
+    let program = parse_program(labeled).unwrap();
+    assert_eq!(disassemble(&program, &Symbols::new()), "set a 3\nsub a 1\njnz a -1\njnz 1 2\nmul a a\nset b a\n");
+    assert_eq!(listing(&program, &Symbols::new()).lines().nth(3), Some("   3: jnz 1 2            ; -> 5"));
+}
+
+#[test]
+fn test_day23_cfg() {
+    let input = format!("set b 57\nset c b\njnz a 2\njnz 1 5\nmul b 100\nsub b -100000\n\
+        set c b\nsub c -17000\n{}", COMPOSITE_LOOP);
+    let program = parse_program(&input).unwrap();
+    let cfg = Cfg::build(&program);
+
+    // Find the loops over b, d and e
+    let loops: Vec<(usize, usize)> = cfg.loops.iter()
+        .map(|l| (cfg.blocks[l.header].start, cfg.blocks[l.latch].end - 1))
+        .collect();
+    assert_eq!(loops, vec![(8, 31), (10, 23), (11, 19)]);
+
+    assert!(cfg.unreachable().is_empty());
+    assert!(cfg.dynamic_jumps.is_empty());
+    assert_eq!(cfg.uninit_reads, vec![(2, 0), (25, 7)]);
+}
+
+// ==== Optimizer ====
+
+// Replace the head of each known loop with a superinstruction
+fn optimize(program: &Program) -> Program {
+    (0..program.len())
+        .map(|pc| match_super(&program[pc..]).map_or(program[pc], Inst::OPT))
+        .collect()
+}
+
+fn match_super(code: &[Inst]) -> Option<Super> {
+    use self::Inst::*;
+    use self::Op::{Reg as R, Int as I};
+
+    // Registers must be distinct for the loop to be well-behaved
+    fn distinct(regs: &[Reg]) -> bool {
+        regs.iter().enumerate().all(|(i, reg)| !regs[i + 1..].contains(reg))
+    }
+
+    // Match the inner loop of COMPOSITE_LOOP
+    let divides = |code: &[Inst]| match *code.get(..9)? {
+        [SET(tmp, R(d)), MUL(tmp1, R(e)), SUB(tmp2, R(n)), JNZ(R(tmp3), I(2)), SET(flag, I(0)),
+            SUB(e1, I(-1)), SET(tmp4, R(e2)), SUB(tmp5, R(n1)), JNZ(R(tmp6), I(-8))]
+            if [tmp1, tmp2, tmp3, tmp4, tmp5, tmp6].iter().all(|&t| t == tmp)
+                && e1 == e && e2 == e && n1 == n && distinct(&[flag, d, e, n, tmp])
+            => Some(Super::Divides { flag, d, e, n, tmp }),
+        _ => None,
+    };
+
+    // Match the middle loop of COMPOSITE_LOOP around the inner loop
+    if let Some(&SET(e, I(init))) = code.first() {
+        let tail = code.get(10..14).unwrap_or(&[]);
+        if let (Some(Super::Divides { flag, d, e: e1, n, tmp }),
+            &[SUB(d1, I(-1)), SET(tmp1, R(d2)), SUB(tmp2, R(n1)), JNZ(R(tmp3), I(-13))]) = (divides(&code[1..]), tail) {
+            if e1 == e && d1 == d && d2 == d && n1 == n && [tmp1, tmp2, tmp3].iter().all(|&t| t == tmp) {
+                return Some(Super::Factors { flag, d, e, n, tmp, init });
+            }
+        }
+    }
+
+    if let Some(divides) = divides(code) {
+        return Some(divides);
+    }
+
+    match *code.get(..3).unwrap_or(&[]) {
+        [SUB(reg, op), SUB(count, I(step)), JNZ(R(count1), I(-2))]
+            if count1 == count && reg != count && op != R(count) && op != R(reg)
+            => return Some(Super::MulSub { reg, op, count, step }),
+        _ => (),
+    }
+
+    match *code.get(..2).unwrap_or(&[]) {
+        [SUB(reg, I(step)), JNZ(R(reg1), I(-1))] if reg1 == reg
+            => Some(Super::Clear { reg, step }),
+        _ => None,
+    }
+}
+
+impl Super {
+    // The instruction replaced by this superinstruction
+    fn fallback(self) -> Inst {
+        match self {
+            Super::MulSub { reg, op, .. } => Inst::SUB(reg, op),
+            Super::Clear { reg, step } => Inst::SUB(reg, Op::Int(step)),
+            Super::Divides { d, tmp, .. } => Inst::SET(tmp, Op::Reg(d)),
+            Super::Factors { e, init, .. } => Inst::SET(e, Op::Int(init)),
+        }
+    }
+}
+
+// Count loop iterations for a counter stepping down to exactly zero
+fn count_steps(count: Int, step: Int) -> Option<Int> {
+    match (count.checked_rem(step)?, count.checked_div(step)?) {
+        (0, steps) if steps > 0 => Some(steps),
+        _ => None,
+    }
+}
+
+// Check whether d * e == n for any d in ds and e in es
+fn has_factors(ds: (Int, Int), es: (Int, Int), n: Int) -> bool {
+    let contains = |(lo, hi): (Int, Int), x: Int| lo <= x && x < hi;
 
+    if n == 0 {
+        return contains(ds, 0) || contains(es, 0);
+    }
+
+    // Try each pair of divisors of n up to sqrt(n)
+    let mut p: Int = 1;
+    while p.checked_mul(p).is_some_and(|p2| p2 <= n.abs()) {
+        if n % p == 0 {
+            let q = n / p;
+            for &(d, e) in &[(p, q), (q, p), (-p, -q), (-q, -p)] {
+                if contains(ds, d) && contains(es, e) {
+                    return true;
+                }
+            }
+        }
+        p += 1;
+    }
+
+    false
+}
+
+#[test]
+fn test_day23_has_factors() {
+    assert!(has_factors((2, 12), (2, 12), 12));
+    assert!(!has_factors((2, 12), (2, 12), 13));
+    assert!(!has_factors((4, 12), (4, 12), 12));
+    assert!(has_factors((-3, 0), (-5, 0), 12));
+    assert!(has_factors((1, 2), (0, 1), 0));
+}
+
+fn run_optimized(program: &Program, arith: Arith) -> Result<Int, Fault<Inst>> {
+    match Machine::fits(program) {
+        true => run_optimized_with::<[Int; REG_COUNT]>(program, arith),
+        false => run_optimized_with::<Vec<Int>>(program, arith),
+    }
+}
+
+fn run_optimized_with<R: Registers>(program: &Program, arith: Arith) -> Result<Int, Fault<Inst>> {
+    let mut machine = Machine::<R>::zeroed();
+    machine.arith = arith;
+    machine.regs[0] = 1;
+    machine.run(&optimize(program))?;
+    Ok(machine.regs[7])
+}
+
+#[test]
+fn test_day23_optimize() {
+    let input = format!("set b 57\nset c b\njnz a 2\njnz 1 5\nmul b 100\nsub b -100000\n\
+        set c b\nsub c -17000\n{}", COMPOSITE_LOOP);
+    let program = parse_program(&input).unwrap();
+    let optimized = optimize(&program);
+    assert_eq!(optimized[10], Inst::OPT(Super::Factors { flag: 5, d: 3, e: 4, n: 1, tmp: 6, init: 2 }));
+    assert_eq!(optimized[11], Inst::OPT(Super::Divides { flag: 5, d: 3, e: 4, n: 1, tmp: 6 }));
+    assert_eq!(run_optimized(&program, Arith::Checked).unwrap(), part2(&program).unwrap());
+
+    // The optimized program should have the same effect as the original
+    let input = "\
+        set c 5\n\
+        sub a -3\n\
+        sub c 1\n\
+        jnz c -2\n\
+        set d -8\n\
+        sub d -2\n\
+        jnz d -1\n\
+        set e 9\n\
+        set b 20\n\
+        set d 2\n\
+        set f 1\n\
+        set g d\n\
+        mul g e\n\
+        sub g b\n\
+        jnz g 2\n\
+        set f 0\n\
+        sub e -1\n\
+        set g e\n\
+        sub g b\n\
+        jnz g -8";
+    let program = parse_program(input).unwrap();
+    let optimized = optimize(&program);
+    assert_eq!(optimized[1], Inst::OPT(Super::MulSub { reg: 0, op: Op::Int(-3), count: 2, step: 1 }));
+    assert_eq!(optimized[5], Inst::OPT(Super::Clear { reg: 3, step: -2 }));
+    assert_eq!(optimized[11], Inst::OPT(Super::Divides { flag: 5, d: 3, e: 4, n: 1, tmp: 6 }));
+
+    let mut expected = Machine::new();
+    expected.run(&program).unwrap();
+    let mut machine = Machine::new();
+    machine.run(&optimized).unwrap();
+    assert_eq!(machine.regs, expected.regs);
+    assert_eq!(machine.mul_count, expected.mul_count);
+    assert_eq!(machine.regs, [15, 20, 0, 2, 20, 0, 0, 0]);
+
+    // Counters which would step past zero fall back to the original loop
+    let mut machine = Machine::new();
+    machine.regs[2] = 5;
+    assert_eq!(machine.apply(Super::Clear { reg: 2, step: 2 }), None);
+    assert_eq!(machine.apply(Super::Clear { reg: 2, step: -1 }), None);
+    assert_eq!(machine.apply(Super::Clear { reg: 2, step: 1 }), Some(2));
+    assert_eq!(machine.regs[2], 0);
+
+    // As do divisors with no successor
+    machine.regs = [0, 1, 0, Int::MAX, 0, 1, 0, 0];
+    assert_eq!(machine.apply(Super::Divides { flag: 5, d: 3, e: 4, n: 1, tmp: 6 }), None);
+    assert_eq!(machine.regs, [0, 1, 0, Int::MAX, 0, 1, 0, 0]);
+}
+
+impl Machine {
+    #[cfg(test)]
     fn new() -> Self {
-        Self { pc: 0, regs: [0; Self::REG_COUNT], mul_count: 0 }
+        Self::zeroed()
+    }
+
+    // Whether a program fits in the fixed array of registers a to h
+    fn fits(program: &Program) -> bool {
+        reg_count(program) <= REG_COUNT
+    }
+}
+
+impl<R: Registers> Machine<R> {
+    fn zeroed() -> Self {
+        Self { pc: 0, regs: R::zeroed(), mul_count: 0, profile: None, arith: Arith::Checked }
+    }
+
+    fn record(&mut self, jumped: bool) {
+        if let Some(ref mut profile) = self.profile {
+            profile.record(self.pc, jumped);
+        }
     }
 
     fn read(&self, op: Op) -> Int {
@@ -141,32 +663,327 @@ impl Machine {
         &mut self.regs[reg as usize]
     }
 
-    fn run(&mut self, program: &Program) {
-        let len = program.len() as Int;
-        while self.pc >= 0 && self.pc < len {
+    fn update<F>(&mut self, reg: usize, value: Int, op: F) -> Result<(), FaultKind>
+        where F: Fn(Arith, Int, Int) -> Result<Int, FaultKind> {
+
+        self.regs[reg] = op(self.arith, self.regs[reg], value)?;
+        Ok(())
+    }
+
+    fn run(&mut self, program: &Program) -> Result<(), Fault<Inst>> {
+        self.run_to(program, program.len() as Int)
+    }
+
+    // Run until the PC leaves the instructions before end, or an instruction faults
+    fn run_to(&mut self, program: &Program, end: Int) -> Result<(), Fault<Inst>> {
+        while self.pc >= 0 && self.pc < end {
             let inst = program[self.pc as usize];
 
+            // Try superinstructions first, or else run the instruction they replaced
+            let inst = match inst {
+                Inst::OPT(opt) => match self.apply(opt) {
+                    Some(len) => {
+                        self.record(true);
+                        self.pc += len;
+                        continue;
+                    },
+                    None => opt.fallback(),
+                },
+                _ => inst,
+            };
+
             // Handle jump first
             if let Inst::JNZ(op1, op2) = inst {
                 if self.read(op1) != 0 {
-                    self.pc += self.read(op2);
+                    self.record(true);
+                    match self.arith.add(self.pc, self.read(op2)) {
+                        Ok(pc) => self.pc = pc,
+                        Err(kind) => return Err(Fault { pc: self.pc, inst, kind }),
+                    }
                     continue;
                 }
             }
 
             // Otherwise, increment PC by 1
+            self.record(false);
             self.pc += 1;
 
-            // Then handle remaining instructions
-            match inst {
-                Inst::SET(reg, op) => *self.rw(reg) = self.read(op),
-                Inst::SUB(reg, op) => *self.rw(reg) -= self.read(op),
-                Inst::MUL(reg, op) => {
-                    *self.rw(reg) *= self.read(op);
-                    self.mul_count += 1;
+            // Then handle remaining instructions, stopping at the faulting instruction
+            if let Err(kind) = self.execute(inst) {
+                self.pc -= 1;
+                return Err(Fault { pc: self.pc, inst, kind });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn execute(&mut self, inst: Inst) -> Result<(), FaultKind> {
+        match inst {
+            Inst::SET(reg, op) => *self.rw(reg) = self.read(op),
+            Inst::SUB(reg, op) => self.update(reg as usize, self.read(op), Arith::sub)?,
+            Inst::MUL(reg, op) => {
+                self.update(reg as usize, self.read(op), Arith::mul)?;
+                self.mul_count += 1;
+            },
+            _ => (),
+        }
+        Ok(())
+    }
+
+    // Run a superinstruction if its loop terminates without overflow, returning its length;
+    // otherwise the original loop runs and faults (or not) according to the arithmetic mode
+    fn apply(&mut self, opt: Super) -> Option<Int> {
+        let regs = &self.regs;
+        let reg = |reg: Reg| regs[reg as usize];
+
+        match opt {
+            Super::MulSub { reg: x, op, count, step } => {
+                let steps = count_steps(reg(count), step)?;
+                let value = reg(x).checked_sub(self.read(op).checked_mul(steps)?)?;
+                *self.rw(x) = value;
+                *self.rw(count) = 0;
+                Some(3)
+            },
+            Super::Clear { reg: x, step } => {
+                count_steps(reg(x), step)?;
+                *self.rw(x) = 0;
+                Some(2)
+            },
+            Super::Divides { flag, d, e, n, tmp } => {
+                let (d, e0, n) = (reg(d), reg(e), reg(n));
+                if e0 >= n {
+                    return None;
+                }
+
+                // Products are linear in e, so checking the ends of the range suffices
+                for &e in &[e0, n - 1] {
+                    d.checked_mul(e)?.checked_sub(n)?;
+                }
+
+                if has_factors((d, d.checked_add(1)?), (e0, n), n) {
+                    *self.rw(flag) = 0;
+                }
+                *self.rw(e) = n;
+                *self.rw(tmp) = 0;
+                self.mul_count += (n - e0) as u64;
+                Some(9)
+            },
+            Super::Factors { flag, d, e, n, tmp, init } => {
+                let (d0, n) = (reg(d), reg(n));
+                if d0 >= n || init >= n {
+                    return None;
+                }
+
+                // Products are bilinear in d and e, so checking the corners suffices
+                for &(d, e) in &[(d0, init), (d0, n - 1), (n - 1, init), (n - 1, n - 1)] {
+                    d.checked_mul(e)?.checked_sub(n)?;
+                    d.checked_add(1)?;
+                }
+                let muls = ((n - d0) as u64).checked_mul((n - init) as u64)?;
+
+                if has_factors((d0, n), (init, n), n) {
+                    *self.rw(flag) = 0;
+                }
+                *self.rw(d) = n;
+                *self.rw(e) = n;
+                *self.rw(tmp) = 0;
+                self.mul_count += muls;
+                Some(14)
+            },
+        }
+    }
+}
+
+// ==== Compiled backend ====
+
+// Instructions with operands decoded and jump targets resolved ahead of time
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Code {
+    SetReg(usize, usize),
+    SetInt(usize, Int),
+    SubReg(usize, usize),
+    SubInt(usize, Int),
+    MulReg(usize, usize),
+    MulInt(usize, Int),
+    Jump(Int), // absolute target
+    JnzReg(usize, Int), // condition register, absolute target
+    JumpBy(usize), // offset register
+    JnzRegBy(usize, usize), // condition register, offset register
+    JumpFar(Int, Int), // constant condition, offset too far for an absolute target
+    JnzRegFar(usize, Int), // condition register, offset too far for an absolute target
+    Nop,
+    Opt(Super),
+}
+
+impl Code {
+    // Recover an arithmetic instruction or relative jump, for reporting faults
+    fn fault_inst(self) -> Inst {
+        match self {
+            Code::SubReg(reg, src) => Inst::SUB(reg as Reg, Op::Reg(src as Reg)),
+            Code::SubInt(reg, int) => Inst::SUB(reg as Reg, Op::Int(int)),
+            Code::MulReg(reg, src) => Inst::MUL(reg as Reg, Op::Reg(src as Reg)),
+            Code::MulInt(reg, int) => Inst::MUL(reg as Reg, Op::Int(int)),
+            Code::JumpBy(offset) => Inst::JNZ(Op::Int(1), Op::Reg(offset as Reg)),
+            Code::JnzRegBy(cond, offset) => Inst::JNZ(Op::Reg(cond as Reg), Op::Reg(offset as Reg)),
+            Code::JumpFar(cond, offset) => Inst::JNZ(Op::Int(cond), Op::Int(offset)),
+            Code::JnzRegFar(cond, offset) => Inst::JNZ(Op::Reg(cond as Reg), Op::Int(offset)),
+            Code::Opt(opt) => opt.fallback(),
+            _ => unreachable!("{:?} is not arithmetic", self),
+        }
+    }
+}
+
+fn compile(program: &Program) -> Vec<Code> {
+    program.iter().enumerate()
+        .map(|(pc, inst)| match *inst {
+            Inst::SET(reg, Op::Reg(src)) => Code::SetReg(reg as usize, src as usize),
+            Inst::SET(reg, Op::Int(int)) => Code::SetInt(reg as usize, int),
+            Inst::SUB(reg, Op::Reg(src)) => Code::SubReg(reg as usize, src as usize),
+            Inst::SUB(reg, Op::Int(int)) => Code::SubInt(reg as usize, int),
+            Inst::MUL(reg, Op::Reg(src)) => Code::MulReg(reg as usize, src as usize),
+            Inst::MUL(reg, Op::Int(int)) => Code::MulInt(reg as usize, int),
+            Inst::JNZ(Op::Int(0), _) => Code::Nop,
+            // Targets past the end of Int depend on the arithmetic mode, so resolve them when taken
+            Inst::JNZ(Op::Int(cond), Op::Int(offset)) => match (pc as Int).checked_add(offset) {
+                Some(target) => Code::Jump(target),
+                None => Code::JumpFar(cond, offset),
+            },
+            Inst::JNZ(Op::Int(_), Op::Reg(offset)) => Code::JumpBy(offset as usize),
+            Inst::JNZ(Op::Reg(cond), Op::Int(offset)) => match (pc as Int).checked_add(offset) {
+                Some(target) => Code::JnzReg(cond as usize, target),
+                None => Code::JnzRegFar(cond as usize, offset),
+            },
+            Inst::JNZ(Op::Reg(cond), Op::Reg(offset)) => Code::JnzRegBy(cond as usize, offset as usize),
+            Inst::OPT(opt) => Code::Opt(opt),
+        })
+        .collect()
+}
+
+impl<R: Registers> Machine<R> {
+    // Same as run, but without profiling
+    fn run_compiled(&mut self, code: &[Code]) -> Result<(), Fault<Inst>> {
+        // Negative PCs wrap around to large indices, so get() handles both bounds
+        while let Some(&code) = code.get(self.pc as usize) {
+            self.pc += 1;
+            let mut result = Ok(());
+            match code {
+                Code::SetReg(reg, src) => self.regs[reg] = self.regs[src],
+                Code::SetInt(reg, int) => self.regs[reg] = int,
+                Code::SubReg(reg, src) => result = self.update(reg, self.regs[src], Arith::sub),
+                Code::SubInt(reg, int) => result = self.update(reg, int, Arith::sub),
+                // Only count multiplications that don't fault
+                Code::MulReg(reg, src) => {
+                    result = self.update(reg, self.regs[src], Arith::mul).map(|()| self.mul_count += 1);
+                },
+                Code::MulInt(reg, int) => {
+                    result = self.update(reg, int, Arith::mul).map(|()| self.mul_count += 1);
+                },
+                Code::Jump(target) => self.pc = target,
+                Code::JnzReg(cond, target) => if self.regs[cond] != 0 { self.pc = target },
+                // Relative jumps are arithmetic on the PC, so they fault like any other
+                Code::JumpBy(offset) => result = self.arith.add(self.pc - 1, self.regs[offset]).map(|target| self.pc = target),
+                Code::JnzRegBy(cond, offset) => if self.regs[cond] != 0 {
+                    result = self.arith.add(self.pc - 1, self.regs[offset]).map(|target| self.pc = target);
+                },
+                Code::JumpFar(_, offset) => {
+                    result = self.arith.add(self.pc - 1, offset).map(|target| self.pc = target);
+                },
+                Code::JnzRegFar(cond, offset) => if self.regs[cond] != 0 {
+                    result = self.arith.add(self.pc - 1, offset).map(|target| self.pc = target);
+                },
+                Code::Nop => (),
+                Code::Opt(opt) => {
+                    // Superinstructions are rare, so just interpret them
+                    self.pc -= 1;
+                    match self.apply(opt) {
+                        Some(len) => self.pc += len,
+                        None => {
+                            self.pc += 1;
+                            result = self.execute(opt.fallback());
+                        },
+                    }
                 },
-                _ => (),
+            }
+
+            // Stop at the faulting instruction
+            if let Err(kind) = result {
+                self.pc -= 1;
+                return Err(Fault { pc: self.pc, inst: code.fault_inst(), kind });
             }
         }
+
+        Ok(())
+    }
+}
+
+type Outcome = (Int, Vec<Int>, u64);
+
+fn run_part1(program: &Program, arith: Arith) -> Result<Outcome, Fault<Inst>> {
+    match Machine::fits(program) {
+        true => run_part1_with::<[Int; REG_COUNT]>(program, arith, false),
+        false => run_part1_with::<Vec<Int>>(program, arith, false),
     }
 }
+
+fn run_part1_compiled(program: &Program, arith: Arith) -> Result<Outcome, Fault<Inst>> {
+    match Machine::fits(program) {
+        true => run_part1_with::<[Int; REG_COUNT]>(program, arith, true),
+        false => run_part1_with::<Vec<Int>>(program, arith, true),
+    }
+}
+
+fn run_part1_with<R: Registers>(program: &Program, arith: Arith, compiled: bool) -> Result<Outcome, Fault<Inst>> {
+    let mut machine = Machine::<R>::zeroed();
+    machine.arith = arith;
+    match compiled {
+        true => machine.run_compiled(&compile(program))?,
+        false => machine.run(program)?,
+    }
+    Ok((machine.pc, machine.regs.as_ref().to_vec(), machine.mul_count))
+}
+
+#[test]
+fn test_day23_compile() {
+    let input = format!("set b 11\nset c b\njnz a 2\njnz 1 2\nsub c -20\n{}",
+        COMPOSITE_LOOP.replace("sub b -17", "sub b -5"));
+    let program = parse_program(&input).unwrap();
+    let code = compile(&program);
+    assert_eq!(code[2], Code::JnzReg(0, 4));
+    assert_eq!(code[3], Code::Jump(5));
+    assert_eq!(run_part1_compiled(&program, Arith::Checked), run_part1(&program, Arith::Checked));
+
+    // Check the optimized program as well as the original
+    let optimized = optimize(&program);
+    for program in &[&program, &optimized] {
+        let mut expected = Machine::new();
+        expected.regs[0] = 1;
+        expected.run(program).unwrap();
+
+        let mut machine = Machine::new();
+        machine.regs[0] = 1;
+        machine.run_compiled(&compile(program)).unwrap();
+        assert_eq!((machine.pc, machine.regs, machine.mul_count), (expected.pc, expected.regs, expected.mul_count));
+    }
+
+    // Check dynamic jumps and jumps off either end
+    let input = "\
+        set a 2\n\
+        jnz 1 a\n\
+        jnz 0 -10\n\
+        set b -5\n\
+        jnz a b";
+    let program = parse_program(input).unwrap();
+    assert_eq!(compile(&program)[1], Code::JumpBy(0));
+    assert_eq!(compile(&program)[2], Code::Nop);
+    assert_eq!(compile(&program)[4], Code::JnzRegBy(0, 1));
+    assert_eq!(run_part1_compiled(&program, Arith::Checked), run_part1(&program, Arith::Checked));
+    assert_eq!(run_part1(&program, Arith::Checked).unwrap().0, -1);
+
+    // Superinstructions fall back to the instruction they replaced
+    let program = optimize(&parse_program("set c 5\nsub a 1\nsub c 2\njnz c -2").unwrap());
+    let mut machine = Machine::new();
+    machine.pc = 1;
+    machine.run_compiled(&compile(&program)[..2]).unwrap();
+    assert_eq!((machine.pc, machine.regs[0]), (2, -1));
+}
@@ -1,66 +1,345 @@
 use std::fs;
 use std::env;
 use std::io;
+use std::cmp::max;
+use std::str::FromStr;
+use std::time::Instant;
 
-fn part1(tokens: &str) -> u32 {
-    // Parse the line into u32 tokens
-    let mut jumps: Vec<i32> = tokens
-        .split_whitespace()
-        .map(|tok| tok.parse::<i32>().expect("expected a number"))
-        .collect();
+// ==== Rules ====
 
-    let mut index = 0;
-    let mut count = 0;
+// How a jump offset changes after the program jumps from it, or None if it overflows
+pub struct Rule(Box<dyn Fn(i32) -> Option<i32>>);
 
-    // Loop until index leaves table
-    while index >= 0 && index < jumps.len() as i32 {
-        // Compute the next index and increment the old one
-        let new_index = index + jumps[index as usize];
-        jumps[index as usize] += 1;
-
-        // Jump and increment the jump count
-        index = new_index;
-        count = count + 1;
+// Named rules, written in the rule language
+const PRESETS: &[(&str, &str)] = &[
+    ("part1", "+1"),
+    ("part2", ">=3 -1; +1"),
+    ("static", "+0"),
+    ("bounce", "=-1"),
+];
+
+impl Rule {
+    pub fn new<F: Fn(i32) -> Option<i32> + 'static>(f: F) -> Self {
+        Rule(Box::new(f))
+    }
+
+    pub fn preset(name: &str) -> Option<Self> {
+        PRESETS.iter()
+            .find(|&&(preset, _)| preset == name)
+            .map(|&(_, rule)| Self::parse(rule).expect("presets should parse"))
+    }
+
+    // Parse a preset name, or clauses like ">=3 -1; +1" where the first matching clause applies;
+    // "+n"/"-n" adjust the offset, "=n" replaces it, and offsets no clause matches are left alone
+    pub fn parse(input: &str) -> Result<Self, io::Error> {
+        if let Some(rule) = Self::preset(input.trim()) {
+            return Ok(rule);
+        }
+
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let number = |token: &str| token.parse::<i32>()
+            .map_err(|_| invalid(format!("Expected number, found {}", token)));
+
+        let mut clauses = Vec::new();
+        for clause in input.split(';') {
+            let tokens: Vec<&str> = clause.split_whitespace().collect();
+            let (cond, action) = match tokens[..] {
+                [action] => (None, action),
+                [cond, action] => (Some(cond), action),
+                _ => return Err(invalid(format!("Expected [condition] action, found {}", clause.trim()))),
+            };
+
+            // Conditions compare the offset against a number
+            let cond = match cond {
+                None => None,
+                Some(cond) => {
+                    let split = cond.find(|c: char| c == '-' || c.is_ascii_digit()).unwrap_or(cond.len());
+                    let test: fn(&i32, &i32) -> bool = match &cond[..split] {
+                        "<" => i32::lt,
+                        "<=" => i32::le,
+                        "==" => i32::eq,
+                        "!=" => i32::ne,
+                        ">=" => i32::ge,
+                        ">" => i32::gt,
+                        op => return Err(invalid(format!("Expected comparison, found {}", op))),
+                    };
+                    Some((test, number(&cond[split..])?))
+                },
+            };
+
+            // Actions either adjust or replace the offset, with a single sign before adjustments
+            let unsigned = |rest: &&str| rest.starts_with(|c: char| c.is_ascii_digit());
+            let action = match action.chars().next() {
+                Some('+') | Some('-') => (false, number(action.strip_prefix('+').filter(unsigned).unwrap_or(action))?),
+                Some('=') => (true, number(&action[1..])?),
+                _ => return Err(invalid(format!("Expected +n, -n or =n, found {}", action))),
+            };
+            clauses.push((cond, action));
+        }
+
+        Ok(Rule::new(move |offset| {
+            clauses.iter()
+                .find(|&&(cond, _)| cond.is_none_or(|(test, value)| test(&offset, &value)))
+                .map_or(Some(offset), |&(_, (replace, value))| if replace { Some(value) } else { offset.checked_add(value) })
+        }))
     }
 
-    count
+    pub fn apply(&self, offset: i32) -> Option<i32> {
+        (self.0)(offset)
+    }
 }
 
 #[test]
-fn test_day5_part1() {
-    assert_eq!(part1("0 3 0 1 -3"), 5);
+fn test_day5_rule() {
+    let part2 = Rule::parse(">=3 -1; +1").unwrap();
+    assert_eq!((part2.apply(2), part2.apply(3), part2.apply(-4)), (Some(3), Some(2), Some(-3)));
+
+    let rule = Rule::parse("<0 =0; ==0 +2").unwrap();
+    assert_eq!((rule.apply(-5), rule.apply(0), rule.apply(7)), (Some(0), Some(2), Some(7)));
+
+    assert_eq!(Rule::preset("part1").unwrap().apply(-1), Some(0));
+    assert_eq!(Rule::preset("part1").unwrap().apply(i32::MAX), None);
+    assert_eq!(Rule::parse("bounce").unwrap().apply(9), Some(-1));
+    assert_eq!(Rule::parse("+3").unwrap().apply(1), Some(4));
+    assert!(Rule::parse("++3").is_err());
+    assert!(Rule::parse("+-3").is_err());
+    assert!(Rule::parse(">=x -1").is_err());
+    assert!(Rule::parse("~3 -1").is_err());
+    assert!(Rule::parse(">=3 -1 +1").is_err());
+    assert!(Rule::preset("nope").is_none());
+}
+
+// ==== Simulation ====
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Exit {
+    Left,
+    Right,
+    Cycle { period: u64 }, // the program never exits
 }
 
-fn part2(tokens: &str) -> u32 {
-    // Parse the line into u32 tokens
-    let mut jumps: Vec<i32> = tokens
-        .split_whitespace()
-        .map(|tok| tok.parse::<i32>().expect("expected a number"))
-        .collect();
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Outcome {
+    pub steps: u64, // steps taken to exit, or to detect the cycle
+    pub exit: Exit,
+    pub max_index: usize, // largest index visited
+}
+
+// Mix a cell into the state hash, which is a sum so single cells can be swapped in and out
+fn mix(index: usize, offset: i32) -> u64 {
+    let x = ((index as u64) << 32 | offset as u32 as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    x ^ (x >> 29)
+}
+
+// Fails if the rule overflows an offset
+pub fn simulate(jumps: &[i32], rule: &Rule) -> Result<Outcome, io::Error> {
+    let mut jumps = jumps.to_vec();
+    let mut hash = jumps.iter().enumerate().fold(0u64, |hash, (i, &offset)| hash.wrapping_add(mix(i, offset)));
+
+    let mut index: i64 = 0;
+    let mut steps = 0;
+    let mut max_index = 0;
 
-    let mut index = 0;
-    let mut count = 0;
+    // Brent's algorithm: compare against a snapshot taken at each power of two steps,
+    // checking the cheap hash first and only comparing whole tables when it matches
+    let mut snapshot = (index, hash, jumps.clone());
+    let mut power = 1;
+    let mut period = 0;
 
     // Loop until index leaves table
-    while index >= 0 && index < jumps.len() as i32 {
-        // Compute the next index
-        let offset = &mut jumps[index as usize];
-        let new_index = index + *offset;
+    while index >= 0 && index < jumps.len() as i64 {
+        // Jump, then update the offset we jumped from
+        let i = index as usize;
+        let offset = jumps[i];
+        let updated = rule.apply(offset).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData,
+            format!("Rule overflows offset {} at index {} after {} steps", offset, i, steps)))?;
+        index += offset as i64;
+        jumps[i] = updated;
+        hash = hash.wrapping_sub(mix(i, offset)).wrapping_add(mix(i, updated));
 
-        // Decrement if offset is 3 or more, otherwise increment
-        *offset += if *offset >= 3 { -1 } else { 1 };
+        steps += 1;
+        max_index = max(max_index, i);
 
-        // Jump and increment the jump count
-        index = new_index;
-        count = count + 1;
+        // Check whether we've returned to the snapshot
+        period += 1;
+        if (index, hash) == (snapshot.0, snapshot.1) && jumps == snapshot.2 {
+            return Ok(Outcome { steps, exit: Exit::Cycle { period }, max_index });
+        }
+        if period == power {
+            snapshot = (index, hash, jumps.clone());
+            power *= 2;
+            period = 0;
+        }
     }
 
-    count
+    let exit = if index < 0 { Exit::Left } else { Exit::Right };
+    Ok(Outcome { steps, exit, max_index })
+}
+
+#[test]
+fn test_day5_simulate() {
+    let jumps = [0, 3, 0, 1, -3];
+    let outcome = simulate(&jumps, &Rule::preset("part1").unwrap()).unwrap();
+    assert_eq!(outcome, Outcome { steps: 5, exit: Exit::Right, max_index: 4 });
+
+    // Closures work as rules too
+    let outcome = simulate(&jumps, &Rule::new(|offset| offset.checked_sub(1))).unwrap();
+    assert_eq!((outcome.exit, outcome.max_index), (Exit::Left, 0));
+
+    // Offsets which never change trap the program at a zero
+    let outcome = simulate(&[1, 0, 5], &Rule::preset("static").unwrap()).unwrap();
+    assert_eq!(outcome.exit, Exit::Cycle { period: 1 });
+    assert_eq!(outcome.max_index, 1);
+
+    // Or bounce the program between two cells, as no clause matches
+    let outcome = simulate(&[1, -1], &Rule::parse("==7 +1").unwrap()).unwrap();
+    assert_eq!(outcome, Outcome { steps: 3, exit: Exit::Cycle { period: 2 }, max_index: 1 });
+
+    // Rules which overflow an offset fail rather than panic
+    assert!(simulate(&[2000000000], &Rule::parse("+1000000000").unwrap()).is_err());
+}
+
+// ==== Large mazes ====
+
+// Same as the part 1 rule, with 64-bit offsets and counts and a single bounds check per step
+pub fn fast_part1(jumps: &[i64]) -> u64 {
+    let mut jumps = jumps.to_vec();
+    let mut index: i64 = 0;
+    let mut steps = 0;
+
+    // Negative indices wrap around to large ones, so get_mut() handles both bounds
+    while let Some(offset) = jumps.get_mut(index as usize) {
+        index += *offset;
+        *offset += 1;
+        steps += 1;
+    }
+
+    steps
+}
+
+// Walk through 8 cells which have settled into 2s and 3s (3s are set bits), entering at each
+// position, giving the toggled cells, the entry position into the next chunk and the steps taken
+fn settled_table() -> Box<[(u8, u8, u8); 256 * 8]> {
+    let mut table = Box::new([(0, 0, 0); 256 * 8]);
+    for (i, slot) in table.iter_mut().enumerate() {
+        let (mut bits, mut pos, mut steps) = ((i >> 3) as u8, i as u8 & 7, 0);
+        while pos < 8 {
+            let offset = 2 + (bits >> pos & 1);
+            bits ^= 1 << pos;
+            pos += offset;
+            steps += 1;
+        }
+        *slot = (bits, pos - 8, steps);
+    }
+    table
+}
+
+// Same as the part 2 rule, skipping through the prefix of cells which have settled into 2s and 3s
+pub fn fast_part2(jumps: &[i64]) -> u64 {
+    let table = settled_table();
+    let mut jumps = jumps.to_vec();
+    let mut index: i64 = 0;
+    let mut steps = 0;
+
+    // Cells before settled are only 2s and 3s, which swap with each other forever;
+    // those cells are tracked in bits rather than jumps
+    let mut bits = vec![0u8; jumps.len() / 8 + 1];
+    let mut settled = 0;
+
+    loop {
+        // Skip through whole chunks of settled cells
+        if index >= 0 && index < (settled & !7) as i64 {
+            let mut entry = index as usize & 7;
+            let chunks = &mut bits[index as usize >> 3..settled >> 3];
+            for chunk in chunks.iter_mut() {
+                let (toggled, next, taken) = table[(*chunk as usize) << 3 | entry];
+                *chunk = toggled;
+                entry = next as usize & 7;
+                steps += taken as u64;
+            }
+            index = ((settled & !7) + entry) as i64;
+        }
+
+        // Step through the last partial chunk of settled cells
+        if index >= 0 && index < settled as i64 {
+            let (chunk, bit) = ((index >> 3) as usize, index & 7);
+            index += 2 + (bits[chunk] >> bit & 1) as i64;
+            bits[chunk] ^= 1 << bit;
+            steps += 1;
+            continue;
+        }
+
+        // Otherwise take a normal step, stopping if we've left the table
+        let offset = match jumps.get_mut(index as usize) {
+            Some(offset) => offset,
+            None => break,
+        };
+        let jump = *offset;
+        *offset += if jump >= 3 { -1 } else { 1 };
+
+        // Grow the settled prefix over any cells which are now 2 or 3
+        if index as usize == settled {
+            while settled < jumps.len() && (jumps[settled] == 2 || jumps[settled] == 3) {
+                bits[settled >> 3] |= ((jumps[settled] == 3) as u8) << (settled & 7);
+                settled += 1;
+            }
+        }
+
+        index += jump;
+        steps += 1;
+    }
+
+    steps
+}
+
+#[test]
+fn test_day5_fast() {
+    assert_eq!(fast_part1(&[0, 3, 0, 1, -3]), 5);
+    assert_eq!(fast_part2(&[0, 3, 0, 1, -3]), 10);
+
+    // Compare against the simulator on tables shaped like real inputs, from a simple LCG
+    let mut seed: u64 = 2017;
+    let mut random = |range: u64| {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (seed >> 33) % range
+    };
+    for &len in &[1, 7, 8, 9, 64, 250] {
+        let jumps: Vec<i64> = (0..len).map(|i| random(i as u64 + 3) as i64 - i as i64).collect();
+        let small: Vec<i32> = jumps.iter().map(|&offset| offset as i32).collect();
+        assert_eq!(fast_part1(&jumps), part1(&small).unwrap());
+        assert_eq!(fast_part2(&jumps), part2(&small).unwrap());
+    }
+
+    // Jumping out to the left works too
+    assert_eq!(fast_part2(&[2, 5, -3, 1]), part2(&[2, 5, -3, 1]).unwrap());
+}
+
+// ==== Exercises and tests ====
+
+fn parse<T: FromStr>(input: &str) -> Result<Vec<T>, io::Error> {
+    input.split_whitespace()
+        .map(|tok| tok.parse().map_err(|_|
+            io::Error::new(io::ErrorKind::InvalidData, format!("Expected a number, found {}", tok))))
+        .collect()
+}
+
+fn part1(jumps: &[i32]) -> Result<u64, io::Error> {
+    Ok(simulate(jumps, &Rule::preset("part1").unwrap())?.steps)
+}
+
+#[test]
+fn test_day5_part1() {
+    assert_eq!(part1(&parse("0 3 0 1 -3").unwrap()).unwrap(), 5);
+}
+
+fn part2(jumps: &[i32]) -> Result<u64, io::Error> {
+    // Decrement if offset is 3 or more, otherwise increment
+    Ok(simulate(jumps, &Rule::preset("part2").unwrap())?.steps)
 }
 
 #[test]
 fn test_day5_part2() {
-    assert_eq!(part2("0 3 0 1 -3"), 10);
+    assert_eq!(part2(&parse("0 3 0 1 -3").unwrap()).unwrap(), 10);
 }
 
 pub fn day5(args: &mut env::Args) -> Result<(), io::Error> {
@@ -70,8 +349,31 @@ pub fn day5(args: &mut env::Args) -> Result<(), io::Error> {
         fs::read_to_string(name)?
     };
 
-    println!("Part 1: {}", part1(&input));
-    println!("Part 2: {}", part2(&input));
+    // Optionally run only the large maze variant
+    let mode = args.next();
+    if mode.as_deref() == Some("fast") {
+        let jumps = parse(&input)?;
+        let start = Instant::now();
+        println!("Part 1: {}", fast_part1(&jumps));
+        println!("Part 2: {}", fast_part2(&jumps));
+        println!("Elapsed: {:.3} ms", start.elapsed().as_secs_f64() * 1e3);
+        return Ok(());
+    }
+
+    let jumps = parse(&input)?;
+    println!("Part 1: {}", part1(&jumps)?);
+    println!("Part 2: {}", part2(&jumps)?);
+
+    // Optionally simulate with a preset or custom rule
+    if let Some(rule) = mode {
+        let outcome = simulate(&jumps, &Rule::parse(&rule)?)?;
+        match outcome.exit {
+            Exit::Cycle { period } => println!("Rule '{}' cycles with period {} (found after {} steps)",
+                rule, period, outcome.steps),
+            exit => println!("Rule '{}' exits {:?} after {} steps", rule, exit, outcome.steps),
+        }
+        println!("Max index: {}", outcome.max_index);
+    }
 
     Ok(())
 }
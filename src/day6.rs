@@ -1,9 +1,12 @@
 use std::fs;
 use std::env;
 use std::io;
-use std::collections::HashSet;
+use std::io::Write;
+use cycle::{find_cycle, Cycle, Method};
 
-fn redistribute(banks: &mut Vec<usize>) {
+// ==== Memory banks ====
+
+fn redistribute(banks: &mut [u64]) {
     debug_assert!(!banks.is_empty());
 
     // Find bank with max blocks, favoring the first occurence
@@ -16,9 +19,10 @@ fn redistribute(banks: &mut Vec<usize>) {
     }
 
     // Remove blocks from max bank
+    let len = banks.len();
     banks[max.0] = 0;
-    let div = max.1 / banks.len();
-    let rem = max.1 % banks.len();
+    let div = max.1 / len as u64;
+    let rem = (max.1 % len as u64) as usize;
 
     // Multiples of banks.len() are added to each element
     if div > 0 {
@@ -27,52 +31,121 @@ fn redistribute(banks: &mut Vec<usize>) {
         }
     }
 
-    // Remainder is redistributed starting from the next bank
+    // Remainder is redistributed starting from the next bank, wrapping around to the front
     let start = max.0 + 1;
-    for i in 0..rem {
-        let index = (start + i) % banks.len();
-        banks[index] += 1;
+    let end = start + rem;
+    let (front, back) = banks.split_at_mut(start);
+    for v in back[..rem.min(len - start)].iter_mut().chain(front[..end.saturating_sub(len)].iter_mut()) {
+        *v += 1;
     }
 }
 
-fn count_cycles(banks: &mut Vec<usize>) -> usize {
+// Each bank configuration, starting with the initial one
+pub struct Redistributions {
+    banks: Vec<u64>,
+}
+
+impl Iterator for Redistributions {
+    type Item = Vec<u64>;
+
+    fn next(&mut self) -> Option<Vec<u64>> {
+        if self.banks.is_empty() {
+            return None;
+        }
+
+        let banks = self.banks.clone();
+        redistribute(&mut self.banks);
+        Some(banks)
+    }
+}
+
+pub fn redistributions(banks: &[u64]) -> Redistributions {
+    Redistributions { banks: banks.to_vec() }
+}
+
+#[test]
+fn test_day6_redistributions() {
+    let configs: Vec<Vec<u64>> = redistributions(&[0, 2, 7, 0]).take(6).collect();
+    assert_eq!(configs, vec![
+        vec![0, 2, 7, 0], vec![2, 4, 1, 2], vec![3, 1, 2, 3],
+        vec![0, 2, 3, 4], vec![1, 3, 4, 1], vec![2, 4, 1, 2],
+    ]);
+    assert_eq!(redistributions(&[]).next(), None);
+
+    // Blocks beyond 32 bits are spread just the same
+    let configs: Vec<Vec<u64>> = redistributions(&[1 << 40, 0, 1]).take(2).collect();
+    assert_eq!(configs[1], vec![(1 << 40) / 3, (1 << 40) / 3 + 1, (1 << 40) / 3 + 1]);
+}
+
+fn bank_cycle(banks: &[u64], method: Method) -> Cycle {
     // Handle trivial case
     if banks.is_empty() {
-        return 0;
+        return Cycle { mu: 0, lambda: 0 };
     }
 
-    // Create a set of bank configurations
-    let mut seen_configs = HashSet::new();
+    find_cycle(&banks.to_vec(), |banks: &mut Vec<u64>| redistribute(banks), method)
+}
 
-    let mut cycles = 0;
-    while seen_configs.insert(banks.clone()) {
-        cycles += 1;
-        redistribute(banks);
+// Write each configuration up to and including the repeat, either as CSV or marking the repeat
+fn write_trajectory<W: Write>(out: &mut W, banks: &[u64], cycle: &Cycle, csv: bool) -> Result<(), io::Error> {
+    if csv {
+        let header: Vec<String> = (0..banks.len()).map(|i| format!("bank{}", i)).collect();
+        writeln!(out, "step,{}", header.join(","))?;
     }
 
-    cycles
+    for (step, config) in redistributions(banks).take(cycle.mu + cycle.lambda + 1).enumerate() {
+        let config: Vec<String> = config.iter().map(|v| v.to_string()).collect();
+        if csv {
+            writeln!(out, "{},{}", step, config.join(","))?;
+        } else {
+            let mark = if step == cycle.mu || step == cycle.mu + cycle.lambda { " <- repeat" } else { "" };
+            writeln!(out, "{:>6}: {}{}", step, config.join(" "), mark)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_day6_trajectory() {
+    let banks = [0, 2, 7, 0];
+    let cycle = bank_cycle(&banks, Method::Brent);
+
+    let mut csv = Vec::new();
+    write_trajectory(&mut csv, &banks, &cycle, true).unwrap();
+    assert_eq!(String::from_utf8(csv).unwrap().lines().collect::<Vec<_>>(), [
+        "step,bank0,bank1,bank2,bank3", "0,0,2,7,0", "1,2,4,1,2", "2,3,1,2,3", "3,0,2,3,4", "4,1,3,4,1", "5,2,4,1,2",
+    ]);
+
+    let mut text = Vec::new();
+    write_trajectory(&mut text, &banks, &cycle, false).unwrap();
+    let text = String::from_utf8(text).unwrap();
+    assert_eq!(text.lines().nth(1), Some("     1: 2 4 1 2 <- repeat"));
+    assert_eq!(text.lines().count(), 6);
 }
 
-fn part1(banks: &Vec<usize>) -> usize {
-    let mut copy = banks.clone();
-    count_cycles(&mut copy)
+// Redistributions until a configuration is seen again
+fn part1(cycle: &Cycle) -> usize {
+    cycle.mu + cycle.lambda
 }
 
 #[test]
 fn test_day6_part1() {
-    assert_eq!(part1(&vec![0, 2, 7, 0]), 5);
+    for &method in &[Method::Brent, Method::Hashing, Method::Fingerprint] {
+        assert_eq!(part1(&bank_cycle(&[0, 2, 7, 0], method)), 5);
+    }
 }
 
-fn part2(banks: &Vec<usize>) -> usize {
-    // Surprise, we just call part1 twice
-    let mut copy = banks.clone();
-    count_cycles(&mut copy);
-    count_cycles(&mut copy)
+// Redistributions between the two sightings of that configuration
+fn part2(cycle: &Cycle) -> usize {
+    cycle.lambda
 }
 
 #[test]
 fn test_day6_part2() {
-    assert_eq!(part2(&vec![0, 2, 7, 0]), 4);
+    for &method in &[Method::Brent, Method::Hashing, Method::Fingerprint] {
+        assert_eq!(part2(&bank_cycle(&[0, 2, 7, 0], method)), 4);
+    }
 }
 
 pub fn day6(args: &mut env::Args) -> Result<(), io::Error> {
@@ -82,14 +155,30 @@ pub fn day6(args: &mut env::Args) -> Result<(), io::Error> {
         fs::read_to_string(name)?
     };
 
-    // Parse each usize token as a memory bank
-    let banks: Vec<usize> = input
+    // Parse each u64 token as a memory bank
+    let banks: Vec<u64> = input
         .split_whitespace()
         .map(|tok| tok.parse().expect("expected a number"))
         .collect();
 
-    println!("Part 1: {}", part1(&banks));
-    println!("Part 2: {}", part2(&banks));
+    // Optionally choose the cycle detection method
+    let mut mode = args.next();
+    let method = match mode.as_deref().and_then(Method::from_name) {
+        Some(method) => { mode = args.next(); method },
+        None => Method::Brent,
+    };
+
+    let cycle = bank_cycle(&banks, method);
+    println!("Part 1: {}", part1(&cycle));
+    println!("Part 2: {}", part2(&cycle));
+
+    // Optionally print the trajectory, or export it as CSV to a file
+    if mode.as_deref() == Some("trace") {
+        match args.next() {
+            Some(name) => write_trajectory(&mut fs::File::create(name)?, &banks, &cycle, true)?,
+            None => write_trajectory(&mut io::stdout().lock(), &banks, &cycle, false)?,
+        }
+    }
 
     Ok(())
 }
@@ -1,7 +1,12 @@
 use std::fs;
 use std::env;
 use std::io;
-use std::collections::HashMap;
+use std::fmt;
+use std::cmp::Reverse;
+use std::convert::TryFrom;
+use std::collections::{BTreeSet, HashMap};
+
+// ==== Parsing ====
 
 #[derive(Clone)]
 struct Tower {
@@ -27,25 +32,28 @@ impl Tower {
 type TowerMap = HashMap<String, Tower>;
 
 trait HashMapExt {
-    fn from_input(input: &str) -> TowerMap;
-    fn find_root(&self) -> String;
-    fn find_imbalance(&self, &str) -> Result<(u32, u32), u32>;
+    fn from_input(input: &str) -> Result<TowerMap, io::Error>;
 }
 
 impl HashMapExt for TowerMap {
-    fn from_input(input: &str) -> TowerMap {
+    fn from_input(input: &str) -> Result<TowerMap, io::Error> {
         let mut tower_map = Self::new();
+        let invalid = |number: usize, message: &str| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", number + 1, message))
+        };
 
         // Parse the input line-by-line
-        for line in input.lines() {
+        for (number, line) in input.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
             // Split line with whitespace and punctuation
             let mut iter = line
                 .split_terminator(|c: char| c.is_whitespace() || c.is_ascii_punctuation())
                 .filter(|s| !s.is_empty());
 
             // Read name and weight, create new Tower
-            let name = iter.next().unwrap();
-            let weight = iter.next().unwrap().parse().unwrap();
+            let name = iter.next().ok_or_else(|| invalid(number, "expected a name"))?;
+            let weight = iter.next()
+                .and_then(|weight| weight.parse().ok())
+                .ok_or_else(|| invalid(number, "expected a weight"))?;
             let mut tower = Tower::with_name(name, weight);
 
             // If there are any children, save their names
@@ -54,81 +62,703 @@ impl HashMapExt for TowerMap {
             }
 
             // Store the tower by name in our map
-            tower_map.insert(name.to_string(), tower);
+            if tower_map.insert(name.to_string(), tower).is_some() {
+                return Err(invalid(number, &format!("duplicate program '{}'", name)));
+            }
+        }
+
+        Ok(tower_map)
+    }
+}
+
+// ==== Tower trees ====
+
+// Reasons a map of programs doesn't form a single tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TowerError {
+    NoRoot,
+    MultipleRoots(Vec<String>),
+    MissingChild { parent: String, child: String },
+    MultipleParents { child: String, parents: Vec<String> },
+    Cycle(Vec<String>),
+}
+
+impl fmt::Display for TowerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TowerError::NoRoot => write!(f, "No root program"),
+            TowerError::MultipleRoots(roots) => write!(f, "Multiple root programs: {}", roots.join(", ")),
+            TowerError::MissingChild { parent, child } =>
+                write!(f, "Program '{}' holds missing program '{}'", parent, child),
+            TowerError::MultipleParents { child, parents } =>
+                write!(f, "Program '{}' is held by {}", child, parents.join(", ")),
+            TowerError::Cycle(names) => write!(f, "Programs hold each other: {} -> {}", names.join(" -> "), names[0]),
+        }
+    }
+}
+
+fn to_error(errors: &[TowerError]) -> io::Error {
+    let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+    io::Error::new(io::ErrorKind::InvalidData, messages.join("\n"))
+}
+
+// What the children of an unbalanced program tell us
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    // Most children agree on the expected total, and these children don't
+    Odd { expected: u64, odd: Vec<String> },
+    // There's no majority total, such as with two children which disagree
+    Ambiguous,
+}
+
+// A program whose children have different total weights
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Imbalance {
+    pub node: String,
+    // The name, own weight and total weight of each child
+    pub children: Vec<(String, u32, u64)>,
+    pub verdict: Verdict,
+}
+
+// Programs in name order, held by index
+pub struct TowerTree {
+    names: Vec<String>,
+    weights: Vec<u32>,
+    children: Vec<Vec<usize>>,
+    totals: Vec<u64>,
+    index: HashMap<String, usize>,
+    root: usize,
+}
+
+impl TowerTree {
+    fn from_map(map: &TowerMap) -> Result<TowerTree, Vec<TowerError>> {
+        let mut names: Vec<String> = map.keys().cloned().collect();
+        names.sort();
+        let index: HashMap<String, usize> = names.iter().enumerate().map(|(i, name)| (name.clone(), i)).collect();
+        let weights = names.iter().map(|name| map[name].weight).collect();
+
+        // Resolve child names, noting any which are missing
+        let mut errors = Vec::new();
+        let mut parents = vec![Vec::new(); names.len()];
+        let mut children = Vec::with_capacity(names.len());
+        for (i, name) in names.iter().enumerate() {
+            let mut held = Vec::new();
+            for child in &map[name].children {
+                match index.get(child) {
+                    Some(&c) => { held.push(c); parents[c].push(i); },
+                    None => errors.push(TowerError::MissingChild { parent: name.clone(), child: child.clone() }),
+                }
+            }
+            children.push(held);
+        }
+
+        for (c, held_by) in parents.iter().enumerate().filter(|(_, held_by)| held_by.len() > 1) {
+            errors.push(TowerError::MultipleParents {
+                child: names[c].clone(),
+                parents: held_by.iter().map(|&p| names[p].clone()).collect(),
+            });
+        }
+
+        errors.extend(find_cycles(&children).into_iter()
+            .map(|cycle| TowerError::Cycle(cycle.into_iter().map(|i| names[i].clone()).collect())));
+
+        // There should be exactly one program which isn't held by any other
+        let roots: Vec<usize> = (0..names.len()).filter(|&i| parents[i].is_empty()).collect();
+        match roots.len() {
+            0 => errors.push(TowerError::NoRoot),
+            1 => (),
+            _ => errors.push(TowerError::MultipleRoots(roots.iter().map(|&i| names[i].clone()).collect())),
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        let mut tower = TowerTree { names, weights, children, totals: Vec::new(), index, root: roots[0] };
+        tower.totals = tower.post_order().iter().fold(vec![0; tower.len()], |mut totals, &i| {
+            totals[i] = tower.weights[i] as u64 + tower.children[i].iter().map(|&c| totals[c]).sum::<u64>();
+            totals
+        });
+        Ok(tower)
+    }
+
+    pub fn parse(input: &str) -> Result<TowerTree, io::Error> {
+        TowerTree::from_map(&TowerMap::from_input(input)?).map_err(|errors| to_error(&errors))
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn root(&self) -> &str {
+        &self.names[self.root]
+    }
+
+    pub fn weight(&self, name: &str) -> Option<u32> {
+        self.index.get(name).map(|&i| self.weights[i])
+    }
+
+    // Weight of a program and everything it holds
+    pub fn total(&self, name: &str) -> Option<u64> {
+        self.index.get(name).map(|&i| self.totals[i])
+    }
+
+    // Programs with children before their parents, without recursing
+    fn post_order(&self) -> Vec<usize> {
+        let mut order = Vec::with_capacity(self.len());
+        let mut stack = vec![self.root];
+        while let Some(i) = stack.pop() {
+            order.push(i);
+            stack.extend(&self.children[i]);
         }
+        order.reverse();
+        order
+    }
+
+    // Every program whose children disagree, deepest first
+    pub fn imbalances(&self) -> Vec<Imbalance> {
+        let mut imbalances = Vec::new();
+        for i in self.post_order() {
+            let totals: Vec<u64> = self.children[i].iter().map(|&c| self.totals[c]).collect();
+            if totals.windows(2).all(|pair| pair[0] == pair[1]) {
+                continue;
+            }
 
-        tower_map
+            // Look for a total shared by more children than any other
+            let mut counts: Vec<(u64, usize)> = Vec::new();
+            for &total in &totals {
+                match counts.iter_mut().find(|(t, _)| *t == total) {
+                    Some((_, count)) => *count += 1,
+                    None => counts.push((total, 1)),
+                }
+            }
+            counts.sort_by_key(|&(_, count)| Reverse(count));
+            let verdict = if counts[0].1 > counts[1].1 {
+                let expected = counts[0].0;
+                let odd = self.children[i].iter()
+                    .filter(|&&c| self.totals[c] != expected)
+                    .map(|&c| self.names[c].clone())
+                    .collect();
+                Verdict::Odd { expected, odd }
+            } else {
+                Verdict::Ambiguous
+            };
+
+            imbalances.push(Imbalance {
+                node: self.names[i].clone(),
+                children: self.children[i].iter()
+                    .map(|&c| (self.names[c].clone(), self.weights[c], self.totals[c]))
+                    .collect(),
+                verdict,
+            });
+        }
+        imbalances
     }
 
-    fn find_root(&self) -> String {
-        let mut towers = self.clone();
-        let mut all_children = Vec::with_capacity(self.len());
+    // The single weight change which balances the deepest unbalanced program
+    pub fn correction(&self) -> Result<(String, u32), io::Error> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let imbalance = self.imbalances().into_iter().next()
+            .ok_or_else(|| invalid("The tower is balanced".to_string()))?;
+
+        match imbalance.verdict {
+            Verdict::Odd { expected, ref odd } if odd.len() == 1 => {
+                let (_, weight, total) = imbalance.children.iter().find(|child| child.0 == odd[0]).unwrap();
+                let corrected = (*weight as u64 + expected).checked_sub(*total)
+                    .ok_or_else(|| invalid(format!("Program '{}' can't be light enough", odd[0])))?;
+                let corrected = u32::try_from(corrected)
+                    .map_err(|_| invalid(format!("Program '{}' would be too heavy", odd[0])))?;
+                Ok((odd[0].clone(), corrected))
+            },
+            Verdict::Odd { ref odd, .. } =>
+                Err(invalid(format!("Programs {} on '{}' are all unbalanced", odd.join(", "), imbalance.node))),
+            Verdict::Ambiguous =>
+                Err(invalid(format!("Can't tell which child of '{}' is unbalanced", imbalance.node))),
+        }
+    }
+}
+
+// Each cycle in a graph, as found by a depth first search
+fn find_cycles(children: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    #[derive(Clone, Copy, PartialEq)]
+    enum Mark { New, Open, Done }
+
+    let mut cycles = Vec::new();
+    let mut marks = vec![Mark::New; children.len()];
+    for start in 0..children.len() {
+        if marks[start] != Mark::New {
+            continue;
+        }
 
-        // Record all names referenced as children
-        for tower in towers.values() {
-            for name in &tower.children {
-                all_children.push(name.to_string());
+        // Each entry is a program and how many of its children we've visited
+        let mut stack = vec![(start, 0)];
+        marks[start] = Mark::Open;
+        while let Some(&mut (i, ref mut next)) = stack.last_mut() {
+            match children[i].get(*next) {
+                Some(&c) => {
+                    *next += 1;
+                    match marks[c] {
+                        Mark::New => { marks[c] = Mark::Open; stack.push((c, 0)); },
+                        Mark::Open => {
+                            let from = stack.iter().position(|&(j, _)| j == c).unwrap();
+                            cycles.push(stack[from..].iter().map(|&(j, _)| j).collect());
+                        },
+                        Mark::Done => (),
+                    }
+                },
+                None => {
+                    marks[i] = Mark::Done;
+                    stack.pop();
+                },
             }
         }
+    }
+    cycles
+}
+
+#[test]
+fn test_day7_tower() {
+    let tower = TowerTree::parse("a (1) -> b, c\nb (2) -> d, e\nc (9)\nd (3)\ne (3)").unwrap();
+    assert_eq!((tower.len(), tower.root()), (5, "a"));
+    assert_eq!((tower.weight("b"), tower.total("b"), tower.total("a")), (Some(2), Some(8), Some(18)));
+    assert_eq!(tower.total("z"), None);
+
+    // Two children which disagree can't be resolved
+    assert_eq!(tower.imbalances(), vec![Imbalance {
+        node: "a".to_string(),
+        children: vec![("b".to_string(), 2, 8), ("c".to_string(), 9, 9)],
+        verdict: Verdict::Ambiguous,
+    }]);
+    assert!(tower.correction().is_err());
+
+    // Every unbalanced program is reported, deepest first
+    let tower = TowerTree::parse("a (1) -> b, c, d\nb (1) -> e, f, g\nc (4)\nd (6)\ne (1)\nf (1)\ng (2)").unwrap();
+    let nodes: Vec<(String, Verdict)> = tower.imbalances().into_iter().map(|i| (i.node, i.verdict)).collect();
+    assert_eq!(nodes, vec![
+        ("b".to_string(), Verdict::Odd { expected: 1, odd: vec!["g".to_string()] }),
+        ("a".to_string(), Verdict::Ambiguous),
+    ]);
+    assert_eq!(tower.correction().unwrap(), ("g".to_string(), 1));
+
+    // Broken maps report every problem
+    let errors = |input| TowerTree::from_map(&TowerMap::from_input(input).unwrap()).err().unwrap();
+    assert_eq!(errors("a (1) -> b, x\nb (1)\nc (1)"), vec![
+        TowerError::MissingChild { parent: "a".to_string(), child: "x".to_string() },
+        TowerError::MultipleRoots(vec!["a".to_string(), "c".to_string()]),
+    ]);
+    assert_eq!(errors("a (1) -> b\nb (1) -> c\nc (1) -> a, b"), vec![
+        TowerError::MultipleParents { child: "b".to_string(), parents: vec!["a".to_string(), "c".to_string()] },
+        TowerError::Cycle(vec!["a".to_string(), "b".to_string(), "c".to_string()]),
+        TowerError::Cycle(vec!["b".to_string(), "c".to_string()]),
+        TowerError::NoRoot,
+    ]);
+    assert!(TowerMap::from_input("a (x)").is_err());
+}
+
+// ==== Rebalancing ====
+
+// Weights stay positive, so changes never set one to 0; unreachable totals cost NONE
+const NONE: u32 = u32::MAX;
+
+// Fewest weight changes below a program which give each of its children the same total s, for
+// every s up to the heaviest total any child reaches without changing its own weight. Past that,
+// each child is only as cheap as changing its weight makes it, so every heavier s costs the same.
+struct Costs {
+    shared: Vec<u32>,
+    // Fewest changes for any shared total up to s
+    prefix: Vec<u32>,
+    beyond: u32,
+}
+
+impl Costs {
+    fn shared(&self, s: u64) -> u32 {
+        self.shared.get(s as usize).cloned().unwrap_or(self.beyond)
+    }
+
+    fn prefix(&self, s: u64) -> u32 {
+        self.prefix.get(s as usize).cloned().unwrap_or_else(|| self.least())
+    }
+
+    fn least(&self) -> u32 {
+        self.prefix[self.prefix.len() - 1].min(self.beyond)
+    }
+
+    // Heaviest listed shared total
+    fn limit(&self) -> u64 {
+        self.shared.len() as u64 - 1
+    }
+
+    // Lightest shared total up to limit with the fewest changes
+    fn lightest(&self, limit: u64) -> u64 {
+        let least = self.prefix(limit);
+        (1..=limit.min(self.limit())).find(|&s| self.shared(s) == least).unwrap_or(self.limit() + 1)
+    }
+}
+
+impl TowerTree {
+    // Fewest changes to a program and everything it holds which give it the total t
+    fn cost(&self, costs: &[Option<Costs>], i: usize, t: u64) -> u32 {
+        debug_assert!(t > 0);
+        let weight = self.weights[i] as u64;
+        let costs = match costs[i] {
+            Some(ref costs) => costs,
+            None => return if t == weight { 0 } else { 1 },
+        };
+
+        // Keep our weight, sharing the rest between the children, or change it to at least 1
+        let k = self.children[i].len() as u64;
+        let keep = if t > weight && (t - weight).is_multiple_of(k) { costs.shared((t - weight) / k) } else { NONE };
+        let change = match (t - 1) / k {
+            0 => NONE,
+            s => costs.prefix(s).saturating_add(1),
+        };
+        keep.min(change)
+    }
+
+    // Heaviest total a program reaches without changing its own weight
+    fn heaviest(&self, costs: &[Option<Costs>], i: usize) -> u64 {
+        let weight = self.weights[i] as u64;
+        costs[i].as_ref().map_or(weight, |costs| weight + self.children[i].len() as u64 * costs.limit())
+    }
+
+    fn costs(&self, costs: &[Option<Costs>], i: usize) -> Costs {
+        let children = &self.children[i];
+        let limit = children.iter().map(|&c| self.heaviest(costs, c)).max().unwrap();
+        let shared: Vec<u32> = (0..=limit)
+            .map(|s| match s {
+                0 => NONE,
+                _ => children.iter().fold(0, |sum: u32, &c| sum.saturating_add(self.cost(costs, c, s))),
+            })
+            .collect();
+        let prefix = shared.iter()
+            .scan(NONE, |least, &cost| {
+                *least = (*least).min(cost);
+                Some(*least)
+            })
+            .collect();
+
+        // Heavier than any child's limit, leaves change and every other child changes its weight
+        let beyond = children.iter().fold(0, |sum: u32, &c| {
+            sum.saturating_add(costs[c].as_ref().map_or(1, |costs| costs.least().saturating_add(1)))
+        });
+
+        Costs { shared, prefix, beyond }
+    }
+
+    // Smallest set of weight changes which balance every program, as (name, old weight, new weight),
+    // keeping every weight positive. Costs are listed for each total up to the heaviest that children
+    // reach, so this takes memory in proportion to the tower's weight.
+    pub fn plan(&self) -> Result<Vec<(String, u32, u32)>, io::Error> {
+        let mut costs: Vec<Option<Costs>> = (0..self.len()).map(|_| None).collect();
+        for i in self.post_order().into_iter().filter(|&i| !self.children[i].is_empty()) {
+            costs[i] = Some(self.costs(&costs, i));
+        }
+
+        let mut plan = Vec::new();
+        let mut change = |i: usize, weight: u64| {
+            let weight = u32::try_from(weight).map_err(|_| io::Error::new(io::ErrorKind::InvalidData,
+                format!("Program '{}' would be too heavy", self.names[i])))?;
+            plan.push((self.names[i].clone(), self.weights[i], weight));
+            Ok::<(), io::Error>(())
+        };
+
+        // The root's total doesn't matter, so keep its weight and pick the lightest cheapest shared total
+        let shared = match costs[self.root] {
+            Some(ref root) => root.lightest(root.limit() + 1),
+            None => return Ok(Vec::new()),
+        };
+
+        // Work back down, choosing a weight and a shared child total for each program
+        let mut stack: Vec<(usize, u64)> = self.children[self.root].iter().map(|&c| (c, shared)).collect();
+        while let Some((i, total)) = stack.pop() {
+            let weight = self.weights[i] as u64;
+            let own = match costs[i] {
+                Some(ref own) => own,
+                None => {
+                    if total != weight {
+                        change(i, total)?;
+                    }
+                    continue;
+                },
+            };
+
+            let k = self.children[i].len() as u64;
+            let keep = total > weight && (total - weight).is_multiple_of(k)
+                && own.shared((total - weight) / k) == self.cost(&costs, i, total);
+            let shared = if keep {
+                (total - weight) / k
+            } else {
+                // Leave at least 1 for our own weight
+                let shared = own.lightest((total - 1) / k);
+                change(i, total - k * shared)?;
+                shared
+            };
+            stack.extend(self.children[i].iter().map(|&c| (c, shared)));
+        }
+
+        plan.sort();
+        Ok(plan)
+    }
+}
+
+#[test]
+fn test_day7_plan() {
+    let plan = |input| TowerTree::parse(input).unwrap().plan().unwrap();
+    let changes = |changes: &[(&str, u32, u32)]| -> Vec<(String, u32, u32)> {
+        changes.iter().map(|&(name, old, new)| (name.to_string(), old, new)).collect()
+    };
+
+    assert_eq!(plan("a (1) -> b, c\nb (2)\nc (2)"), changes(&[]));
+    assert_eq!(plan("a (1) -> b, c, d\nb (1)\nc (2)\nd (1)"), changes(&[("c", 2, 1)]));
+
+    // Faults in separate subtrees are all fixed
+    assert_eq!(plan("a (1) -> b, c, d\nb (1) -> e, f, g\nc (4)\nd (6)\ne (1)\nf (1)\ng (2)"),
+        changes(&[("d", 6, 4), ("g", 2, 1)]));
+
+    // Two disagreeing children need one change, so pick either
+    assert_eq!(plan("a (1) -> b, c\nb (2)\nc (3)").len(), 1);
+
+    // Lightening b's children would take three changes, so make its siblings heavier instead
+    assert_eq!(plan("a (1) -> b, c, d\nb (1) -> e, f, g\nc (7)\nd (7)\ne (3)\nf (3)\ng (3)"),
+        changes(&[("c", 7, 10), ("d", 7, 10)]));
+
+    // Weights stay positive, so b can't lose f's weight; of the three changes needed, pick the lightest
+    assert_eq!(plan("a (4) -> b, c, d, e\nb (3) -> f\nc (3)\nd (3)\ne (4)\nf (4)"),
+        changes(&[("b", 3, 2), ("e", 4, 3), ("f", 4, 1)]));
+
+    // Changing a parent is cheaper than changing all of its children
+    assert_eq!(plan("a (1) -> b, c, d\nb (4) -> e, f\nc (6)\nd (6)\ne (2)\nf (2)"),
+        changes(&[("b", 4, 2)]));
+}
+
+#[test]
+fn test_day7_plan_minimal() {
+    // Fewest changes found by trying every set of programs to change, with totals up to a bound
+    fn fewest(tower: &TowerTree, bound: usize) -> usize {
+        (0..1u32 << tower.len())
+            .filter(|changed| {
+                let mut totals: Vec<Vec<bool>> = vec![Vec::new(); tower.len()];
+                for i in tower.post_order() {
+                    let (k, weight) = (tower.children[i].len(), tower.weights[i] as usize);
+                    let shared: Vec<usize> = (1..=bound)
+                        .filter(|&s| tower.children[i].iter().all(|&c| totals[c][s]))
+                        .collect();
+
+                    let mut reachable = vec![false; bound + 1];
+                    match (changed >> i & 1 == 1, k) {
+                        (false, 0) => reachable[weight] = true,
+                        (true, 0) => reachable[1..].iter_mut().for_each(|t| *t = true),
+                        (false, _) => shared.iter().filter(|&&s| weight + k * s <= bound)
+                            .for_each(|&s| reachable[weight + k * s] = true),
+                        (true, _) => if let Some(&s) = shared.first() {
+                            reachable.iter_mut().skip(1 + k * s).for_each(|t| *t = true);
+                        },
+                    }
+                    totals[i] = reachable;
+                }
+                totals[tower.root].contains(&true)
+            })
+            .map(|changed| changed.count_ones() as usize)
+            .min()
+            .unwrap()
+    }
 
-        // Remove all children from the set
-        for name in &all_children {
-            towers.remove(name).unwrap();
+    // Random towers of up to 7 programs with weights 1 to 4, from a simple LCG
+    let mut seed: u64 = 2017;
+    let mut random = |range: u64| {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (seed >> 33) % range
+    };
+    for _ in 0..200 {
+        let n = 2 + random(6) as usize;
+        let parents: Vec<usize> = (1..n).map(|i| random(i as u64) as usize).collect();
+        let mut weights: Vec<u32> = (0..n).map(|_| 1 + random(4) as u32).collect();
+        let input = |weights: &[u32]| -> String {
+            (0..n).map(|i| {
+                let held: Vec<String> = (1..n).filter(|&c| parents[c - 1] == i).map(|c| format!("p{}", c)).collect();
+                match held.len() {
+                    0 => format!("p{} ({})\n", i, weights[i]),
+                    _ => format!("p{} ({}) -> {}\n", i, weights[i], held.join(", ")),
+                }
+            }).collect()
+        };
+
+        let tower = TowerTree::parse(&input(&weights)).unwrap();
+        let plan = tower.plan().unwrap();
+        assert_eq!(plan.len(), fewest(&tower, 120), "{}", input(&weights));
+
+        // Applying the plan balances the tower
+        for (name, old, new) in plan {
+            let i: usize = name[1..].parse().unwrap();
+            assert!(weights[i] == old && old != new && new > 0);
+            weights[i] = new;
         }
+        assert_eq!(TowerTree::parse(&input(&weights)).unwrap().imbalances(), vec![]);
+    }
+}
 
-        // Return the last key in the set
-        assert_eq!(towers.len(), 1);
-        towers.keys().next().unwrap().to_string()
+// ==== Rendering ====
+
+impl TowerTree {
+    // Flag unbalanced programs, and the total each odd child of those should have
+    fn marks(&self) -> (Vec<bool>, Vec<Option<u64>>) {
+        let mut unbalanced = vec![false; self.len()];
+        let mut expected = vec![None; self.len()];
+        for imbalance in self.imbalances() {
+            unbalanced[self.index[&imbalance.node]] = true;
+            if let Verdict::Odd { expected: total, odd } = imbalance.verdict {
+                for name in odd {
+                    expected[self.index[&name]] = Some(total);
+                }
+            }
+        }
+        (unbalanced, expected)
     }
 
-    fn find_imbalance(&self, root_name: &str) -> Result<(u32, u32), u32> {
-        // Get the root tower
-        let tower = self.get(root_name).unwrap();
+    // Graphviz graph, with unbalanced programs and odd branches in red
+    pub fn to_dot(&self) -> String {
+        let (unbalanced, expected) = self.marks();
+        let mut dot = String::from("digraph tower {\n    node [shape=box, fontname=monospace];\n");
 
-        // A state machine to find a consensus between values
-        enum Consensus<T> {
-            None,
-            Propose((T, T)),
-            Disagree(((T, T), (T, T))),
-            Agree(T)
+        for (i, name) in self.names.iter().enumerate() {
+            let mut label = format!("{}\\n{} / {}", name, self.weights[i], self.totals[i]);
+            if let Some(total) = expected[i] {
+                label.push_str(&format!("\\nexpected {}", total));
+            }
+            let style = if unbalanced[i] {
+                ", color=red, penwidth=2"
+            } else if expected[i].is_some() {
+                ", color=red, fontcolor=red"
+            } else {
+                ""
+            };
+            dot.push_str(&format!("    \"{}\" [label=\"{}\"{}];\n", name, label, style));
         }
 
-        // Sum weight over each child
-        let mut sum_children = 0;
-        let mut consensus = Consensus::None;
-        for name in &tower.children {
-            // Return immediately if we found an imbalance
-            let weights = self.find_imbalance(name)?;
-            let total = weights.0 + weights.1;
-            sum_children += total;
+        for i in 0..self.len() {
+            for &c in &self.children[i] {
+                let style = if expected[c].is_some() { " [color=red, penwidth=2]" } else { "" };
+                dot.push_str(&format!("    \"{}\" -> \"{}\"{};\n", self.names[i], self.names[c], style));
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
 
-            // Update the state machine, looking for an imbalance
-            consensus = match consensus {
-                Consensus::None => Consensus::Propose(weights),
-                Consensus::Propose(w) if (w.0 + w.1) == total => Consensus::Agree(total),
-                Consensus::Propose(other) => Consensus::Disagree((other, weights)),
-                Consensus::Agree(w) if w == total => Consensus::Agree(w),
-                Consensus::Agree(w) => return Err(w - weights.1),
-                Consensus::Disagree((a, b)) if (a.0 + a.1) == total => return Err(total - b.1),
-                Consensus::Disagree((a, b)) if (b.0 + b.1) == total => return Err(total - a.1),
-                Consensus::Disagree(_) => panic!("multiple imbalances detected"),
+    // Indented tree of programs as "name (weight, total N)", marking unbalanced programs and odd branches
+    pub fn to_ascii(&self) -> String {
+        let (unbalanced, expected) = self.marks();
+        let mut ascii = String::new();
+
+        // Each entry is a program, the prefix for its line, and the prefix for its children's lines
+        let mut stack = vec![(self.root, String::new(), String::new())];
+        while let Some((i, prefix, indent)) = stack.pop() {
+            ascii.push_str(&format!("{}{} ({}, total {})", prefix, self.names[i], self.weights[i], self.totals[i]));
+            if unbalanced[i] {
+                ascii.push_str(" [unbalanced]");
+            }
+            if let Some(total) = expected[i] {
+                ascii.push_str(&format!(" [expected {}]", total));
+            }
+            ascii.push('\n');
+
+            // Push in reverse so the first child is popped first
+            let last = self.children[i].len().saturating_sub(1);
+            for (n, &c) in self.children[i].iter().enumerate().rev() {
+                let (branch, rest) = if n == last { ("`-- ", "    ") } else { ("|-- ", "|   ") };
+                stack.push((c, format!("{}{}", indent, branch), format!("{}{}", indent, rest)));
             }
         }
 
-        // Just panic if we're unable to resolve which child is imbalanced
-        if let Consensus::Disagree(_) = consensus {
-            panic!("unable to resolve imbalance");
+        ascii
+    }
+}
+
+// Graphviz graph of a map which doesn't form a tree, with the programs and holds at fault in red
+fn broken_to_dot(map: &TowerMap, errors: &[TowerError]) -> String {
+    let mut faulty = BTreeSet::new();
+    let mut missing = BTreeSet::new();
+    let mut holds = BTreeSet::new();
+    for error in errors {
+        match error {
+            TowerError::NoRoot => (),
+            TowerError::MultipleRoots(roots) => faulty.extend(roots.iter().cloned()),
+            TowerError::MissingChild { parent, child } => {
+                missing.insert(child.clone());
+                holds.insert((parent.clone(), child.clone()));
+            },
+            TowerError::MultipleParents { child, parents } => {
+                faulty.insert(child.clone());
+                holds.extend(parents.iter().map(|parent| (parent.clone(), child.clone())));
+            },
+            TowerError::Cycle(names) => {
+                faulty.extend(names.iter().cloned());
+                let next = names.iter().cycle().skip(1);
+                holds.extend(names.iter().zip(next).map(|(parent, child)| (parent.clone(), child.clone())));
+            },
         }
+    }
+
+    let mut names: Vec<&String> = map.keys().collect();
+    names.sort();
+    let mut dot = String::from("digraph tower {\n    node [shape=box, fontname=monospace];\n");
+    for &name in &names {
+        let style = if faulty.contains(name) { ", color=red, penwidth=2" } else { "" };
+        dot.push_str(&format!("    \"{}\" [label=\"{}\\n{}\"{}];\n", name, name, map[name].weight, style));
+    }
+    for name in &missing {
+        dot.push_str(&format!("    \"{}\" [label=\"{}\\nmissing\", style=dashed, color=red, fontcolor=red];\n", name, name));
+    }
 
-        Ok((tower.weight, sum_children))
+    for &name in &names {
+        for child in &map[name].children {
+            let style = if holds.contains(&(name.clone(), child.clone())) { " [color=red, penwidth=2]" } else { "" };
+            dot.push_str(&format!("    \"{}\" -> \"{}\"{};\n", name, child, style));
+        }
     }
+
+    dot.push_str("}\n");
+    dot
 }
 
-fn dewit(input: &str) -> (String, u32) {
-    let towers = TowerMap::from_input(input);
-    let root = towers.find_root();
-    let weight = towers.find_imbalance(&root).unwrap_err();
-    (root, weight)
+#[test]
+fn test_day7_render() {
+    let tower = TowerTree::parse("a (1) -> b, c, d\nb (1) -> e, f, g\nc (5)\nd (4)\ne (1)\nf (1)\ng (2)").unwrap();
+    assert_eq!(tower.to_ascii(), "\
+a (1, total 15) [unbalanced]
+|-- b (1, total 5) [unbalanced]
+|   |-- e (1, total 1)
+|   |-- f (1, total 1)
+|   `-- g (2, total 2) [expected 1]
+|-- c (5, total 5)
+`-- d (4, total 4) [expected 5]
+");
+
+    let dot = tower.to_dot();
+    assert!(dot.starts_with("digraph tower {\n"));
+    assert!(dot.contains("    \"b\" [label=\"b\\n1 / 5\", color=red, penwidth=2];\n"));
+    assert!(dot.contains("    \"g\" [label=\"g\\n2 / 2\\nexpected 1\", color=red, fontcolor=red];\n"));
+    assert!(dot.contains("    \"a\" -> \"c\";\n"));
+    assert!(dot.contains("    \"a\" -> \"d\" [color=red, penwidth=2];\n"));
+    assert!(dot.ends_with("}\n"));
+
+    // Maps which aren't trees are drawn as they are, showing what's wrong
+    let map = TowerMap::from_input("a (1) -> b, x\nb (2) -> c\nc (3) -> b\nd (4)").unwrap();
+    let dot = broken_to_dot(&map, &TowerTree::from_map(&map).err().unwrap());
+    assert!(dot.contains("    \"a\" [label=\"a\\n1\", color=red, penwidth=2];\n"));
+    assert!(dot.contains("    \"b\" [label=\"b\\n2\", color=red, penwidth=2];\n"));
+    assert!(dot.contains("    \"x\" [label=\"x\\nmissing\", style=dashed, color=red, fontcolor=red];\n"));
+    assert!(dot.contains("    \"a\" -> \"x\" [color=red, penwidth=2];\n"));
+    assert!(dot.contains("    \"c\" -> \"b\" [color=red, penwidth=2];\n"));
+}
+
+// ==== Exercises and tests ====
+
+fn dewit(input: &str) -> Result<(String, u32), io::Error> {
+    let tower = TowerTree::parse(input)?;
+    let (_, weight) = tower.correction()?;
+    Ok((tower.root().to_string(), weight))
 }
 
 #[test]
@@ -147,7 +777,7 @@ jptl (61)
 ugml (68) -> gyxo, ebii, jptl
 gyxo (61)
 cntj (57)";
-    assert_eq!(dewit(&input), ("tknk".to_string(), 60));
+    assert_eq!(dewit(&input).unwrap(), ("tknk".to_string(), 60));
 }
 
 pub fn day7(args: &mut env::Args) -> Result<(), io::Error> {
@@ -157,8 +787,45 @@ pub fn day7(args: &mut env::Args) -> Result<(), io::Error> {
         fs::read_to_string(name)?
     };
 
-    let (part1, part2) = dewit(&input);
-    println!("Part 1: {}\nPart 2: {}", part1, part2);
+    // Only print answers for towers with a single correction, but still run the diagnostics on
+    // the rest, then report why there was no answer
+    let result = dewit(&input);
+    if let Ok((part1, part2)) = &result {
+        println!("Part 1: {}\nPart 2: {}", part1, part2);
+    }
+
+    // Optionally report every unbalanced program, or render the tower
+    match args.next().as_deref() {
+        Some("dot") => {
+            let map = TowerMap::from_input(&input)?;
+            match TowerTree::from_map(&map) {
+                Ok(tower) => print!("{}", tower.to_dot()),
+                Err(errors) => print!("{}", broken_to_dot(&map, &errors)),
+            }
+        },
+        Some("tree") => print!("{}", TowerTree::parse(&input)?.to_ascii()),
+        Some("plan") => {
+            for (name, old, new) in TowerTree::parse(&input)?.plan()? {
+                println!("{}: {} -> {}", name, old, new);
+            }
+        },
+        Some("report") => {
+            let tower = TowerTree::parse(&input)?;
+            for imbalance in tower.imbalances() {
+                let node = &imbalance.node;
+                match imbalance.verdict {
+                    Verdict::Odd { expected, ref odd } => println!("{} ({}, total {}): {} should total {}",
+                        node, tower.weight(node).unwrap(), tower.total(node).unwrap(), odd.join(", "), expected),
+                    Verdict::Ambiguous => println!("{} ({}, total {}): ambiguous",
+                        node, tower.weight(node).unwrap(), tower.total(node).unwrap()),
+                }
+                for (name, weight, total) in &imbalance.children {
+                    println!("    {} ({}, total {})", name, weight, total);
+                }
+            }
+        },
+        _ => (),
+    }
 
-    Ok(())
+    result.map(|_| ())
 }
@@ -1,58 +1,631 @@
 use std::fs;
 use std::env;
+use std::fmt;
 use std::io;
-use std::ops::{Add, Sub};
-use std::cmp::max;
+use std::cmp::{max, Reverse};
 use std::collections::HashMap;
 
-fn dewit(input: &str) -> (i32, i32) {
-    let mut regs = HashMap::new();
-    let mut max_value = 0;
-
-    // Parse the input line-by-line
-    for line in input.lines() {
-        // Split line with whitespace
-        let mut iter = line.split_whitespace();
-
-        // Parse the instruction
-        let name = iter.next().unwrap();
-        let op = match iter.next() {
-            Some("inc") => i32::add,
-            Some("dec") => i32::sub,
-            _ => panic!("invalid operation"),
+// ==== Tokens ====
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    Name(String),
+    Int(i64),
+    Plus,
+    Minus,
+    Star,
+    Open,
+    Close,
+    Cmp(CmpOp),
+    And,
+    Or,
+}
+
+// Split a line into tokens, each with its 1-based column, counted in bytes
+fn lex(line: &str, number: usize) -> Result<Vec<(usize, Token)>, Diagnostic> {
+    let mut tokens = Vec::new();
+    let mut chars = line.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        let column = start + 1;
+        let token = match c {
+            _ if c.is_whitespace() => continue,
+            _ if c.is_ascii_alphabetic() || c == '_' => {
+                let mut end = start + 1;
+                while let Some(&(i, c)) = chars.peek() {
+                    if !c.is_ascii_alphanumeric() && c != '_' {
+                        break;
+                    }
+                    end = i + 1;
+                    chars.next();
+                }
+                Token::Name(line[start..end].to_string())
+            },
+            _ if c.is_ascii_digit() => {
+                let mut end = start + 1;
+                while let Some(&(i, c)) = chars.peek() {
+                    if !c.is_ascii_digit() {
+                        break;
+                    }
+                    end = i + 1;
+                    chars.next();
+                }
+                match line[start..end].parse() {
+                    Ok(int) => Token::Int(int),
+                    Err(_) => return Err(Diagnostic::new(number, column,
+                        format!("Integer {} is too large", &line[start..end]))),
+                }
+            },
+            '+' => Token::Plus,
+            '-' => Token::Minus,
+            '*' => Token::Star,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            _ => {
+                // Operators of up to two characters
+                let next = chars.peek().map(|&(_, c)| c);
+                let (token, pair) = match (c, next) {
+                    ('<', Some('=')) => (Token::Cmp(CmpOp::Le), true),
+                    ('>', Some('=')) => (Token::Cmp(CmpOp::Ge), true),
+                    ('=', Some('=')) => (Token::Cmp(CmpOp::Eq), true),
+                    ('!', Some('=')) => (Token::Cmp(CmpOp::Ne), true),
+                    ('&', Some('&')) => (Token::And, true),
+                    ('|', Some('|')) => (Token::Or, true),
+                    ('<', _) => (Token::Cmp(CmpOp::Lt), false),
+                    ('>', _) => (Token::Cmp(CmpOp::Gt), false),
+                    _ => return Err(Diagnostic::new(number, column, format!("Unexpected character '{}'", c))),
+                };
+                if pair {
+                    chars.next();
+                }
+                token
+            },
+        };
+        tokens.push((column, token));
+    }
+
+    Ok(tokens)
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Token::Name(ref name) => write!(f, "{}", name),
+            Token::Int(int) => write!(f, "{}", int),
+            Token::Plus => write!(f, "+"),
+            Token::Minus => write!(f, "-"),
+            Token::Star => write!(f, "*"),
+            Token::Open => write!(f, "("),
+            Token::Close => write!(f, ")"),
+            Token::Cmp(op) => write!(f, "{}", op),
+            Token::And => write!(f, "&&"),
+            Token::Or => write!(f, "||"),
+        }
+    }
+}
+
+// ==== Syntax ====
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub(crate) enum BinOp {
+    Add,
+    Sub,
+    Mul,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub(crate) enum CmpOp {
+    Lt,
+    Le,
+    Eq,
+    Ne,
+    Ge,
+    Gt,
+}
+
+impl fmt::Display for CmpOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match *self {
+            CmpOp::Lt => "<",
+            CmpOp::Le => "<=",
+            CmpOp::Eq => "==",
+            CmpOp::Ne => "!=",
+            CmpOp::Ge => ">=",
+            CmpOp::Gt => ">",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) enum Expr {
+    Int(i64),
+    Reg(String),
+    Neg(Box<Expr>),
+    Binary(Box<Expr>, BinOp, Box<Expr>),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) enum Cond {
+    Compare(Expr, CmpOp, Expr),
+    And(Box<Cond>, Box<Cond>),
+    Or(Box<Cond>, Box<Cond>),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) struct Stmt {
+    pub(crate) line: usize,
+    pub(crate) reg: String,
+    pub(crate) amount: Expr, // negated for dec
+    pub(crate) cond: Cond,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) struct Program {
+    pub(crate) stmts: Vec<Stmt>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) struct Diagnostic {
+    pub(crate) line: usize,
+    pub(crate) column: usize, // 1-based, counted in bytes, or 0 if not tied to a column
+    pub(crate) message: String,
+}
+
+impl Diagnostic {
+    fn new(line: usize, column: usize, message: String) -> Self {
+        Self { line, column, message }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.column {
+            0 => write!(f, "line {}: {}", self.line, self.message),
+            column => write!(f, "line {}, column {}: {}", self.line, column, self.message),
+        }
+    }
+}
+
+// Join diagnostics into a single error
+fn to_error(diagnostics: &[Diagnostic]) -> io::Error {
+    let messages: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+    io::Error::new(io::ErrorKind::InvalidData, messages.join("\n"))
+}
+
+// Recursive descent over the tokens of a single line
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    line: usize,
+    end: usize, // column just past the end of the line
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, token)| token)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek().cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn error(&self, expected: &str) -> Diagnostic {
+        match self.tokens.get(self.pos) {
+            Some(&(column, ref token)) => Diagnostic::new(self.line, column,
+                format!("Expected {}, found '{}'", expected, token)),
+            None => Diagnostic::new(self.line, self.end, format!("Expected {}, found end of line", expected)),
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str) -> Result<(), Diagnostic> {
+        match self.peek() {
+            Some(Token::Name(name)) if name == keyword => (),
+            _ => return Err(self.error(&format!("'{}'", keyword))),
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    // stmt := name ("inc" | "dec") expr "if" cond
+    fn stmt(&mut self) -> Result<Stmt, Diagnostic> {
+        let reg = match self.peek() {
+            Some(Token::Name(name)) => name.clone(),
+            _ => return Err(self.error("register")),
         };
-        let amount: i32 = iter.next().unwrap().parse().unwrap();
-
-        // Assume this token will always match "if"
-        assert_eq!(iter.next().unwrap(), "if");
-
-        // Parse the condition
-        let left = *regs.get(iter.next().unwrap()).unwrap_or(&0);
-        let cond = match iter.next() {
-            Some("<") => i32::lt,
-            Some("<=") => i32::le,
-            Some("==") => i32::eq,
-            Some("!=") => i32::ne,
-            Some(">=") => i32::ge,
-            Some(">") => i32::gt,
-            _ => panic!("invalid condition"),
+        self.pos += 1;
+
+        let negate = match self.peek() {
+            Some(Token::Name(name)) if name == "inc" => false,
+            Some(Token::Name(name)) if name == "dec" => true,
+            _ => return Err(self.error("'inc' or 'dec'")),
         };
-        let right = iter.next().unwrap().parse().unwrap();
+        self.pos += 1;
 
-        // Evaluate the instruction
-        if cond(&left, &right) {
-            // Update the register value
-            let mut value = *regs.get(name).unwrap_or(&0);
-            value = op(value, amount);
-            regs.insert(name, value);
+        let mut amount = self.expr()?;
+        if negate {
+            amount = Expr::Neg(Box::new(amount));
+        }
+
+        self.keyword("if")?;
+        let cond = self.cond()?;
+        if self.peek().is_some() {
+            return Err(self.error("end of line"));
+        }
+
+        Ok(Stmt { line: self.line, reg, amount, cond })
+    }
 
-            // Record the max value seen
-            max_value = max(max_value, value);
+    // cond := all ("||" all)*
+    fn cond(&mut self) -> Result<Cond, Diagnostic> {
+        let mut cond = self.all()?;
+        while let Some(&Token::Or) = self.peek() {
+            self.pos += 1;
+            cond = Cond::Or(Box::new(cond), Box::new(self.all()?));
         }
+        Ok(cond)
     }
 
+    // all := compare ("&&" compare)*
+    fn all(&mut self) -> Result<Cond, Diagnostic> {
+        let mut cond = self.compare()?;
+        while let Some(&Token::And) = self.peek() {
+            self.pos += 1;
+            cond = Cond::And(Box::new(cond), Box::new(self.compare()?));
+        }
+        Ok(cond)
+    }
+
+    // compare := expr cmp expr
+    fn compare(&mut self) -> Result<Cond, Diagnostic> {
+        let left = self.expr()?;
+        let op = match self.peek() {
+            Some(&Token::Cmp(op)) => op,
+            _ => return Err(self.error("comparison")),
+        };
+        self.pos += 1;
+        Ok(Cond::Compare(left, op, self.expr()?))
+    }
+
+    // expr := term (("+" | "-") term)*
+    fn expr(&mut self) -> Result<Expr, Diagnostic> {
+        let mut expr = self.term()?;
+        loop {
+            let op = match self.peek() {
+                Some(&Token::Plus) => BinOp::Add,
+                Some(&Token::Minus) => BinOp::Sub,
+                _ => return Ok(expr),
+            };
+            self.pos += 1;
+            expr = Expr::Binary(Box::new(expr), op, Box::new(self.term()?));
+        }
+    }
+
+    // term := factor ("*" factor)*
+    fn term(&mut self) -> Result<Expr, Diagnostic> {
+        let mut expr = self.factor()?;
+        while let Some(&Token::Star) = self.peek() {
+            self.pos += 1;
+            expr = Expr::Binary(Box::new(expr), BinOp::Mul, Box::new(self.factor()?));
+        }
+        Ok(expr)
+    }
+
+    // factor := int | name | "-" factor | "(" expr ")"
+    fn factor(&mut self) -> Result<Expr, Diagnostic> {
+        let error = self.error("operand");
+        match self.next() {
+            Some(Token::Int(int)) => Ok(Expr::Int(int)),
+            Some(Token::Name(name)) => Ok(Expr::Reg(name)),
+            Some(Token::Minus) => match self.factor()? {
+                Expr::Int(int) => Ok(Expr::Int(-int)),
+                expr => Ok(Expr::Neg(Box::new(expr))),
+            },
+            Some(Token::Open) => {
+                let expr = self.expr()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => {
+                        self.pos -= 1;
+                        Err(self.error("')'"))
+                    },
+                }
+            },
+            _ => Err(error),
+        }
+    }
+}
+
+impl Program {
+    // Parse every line, collecting a diagnostic for each line which fails
+    pub(crate) fn parse(input: &str) -> Result<Self, Vec<Diagnostic>> {
+        let mut stmts = Vec::new();
+        let mut diagnostics = Vec::new();
+
+        for (i, line) in input.lines().enumerate() {
+            let number = i + 1;
+            let parsed = lex(line, number).and_then(|tokens| {
+                let mut parser = Parser { tokens, pos: 0, line: number, end: line.len() + 1 };
+                parser.stmt()
+            });
+
+            match parsed {
+                // Skip blank lines rather than complaining about them
+                Err(_) if line.trim().is_empty() => (),
+                Ok(stmt) => stmts.push(stmt),
+                Err(diagnostic) => diagnostics.push(diagnostic),
+            }
+        }
+
+        match diagnostics.is_empty() {
+            true => Ok(Program { stmts }),
+            false => Err(diagnostics),
+        }
+    }
+}
+
+#[test]
+fn test_day8_parse() {
+    let program = Program::parse("b inc 5 if a > 1\n\nc dec a * (b - 2) if a >= b && c != -1 || d < 0").unwrap();
+    assert_eq!(program.stmts.len(), 2);
+    assert_eq!(program.stmts[0], Stmt {
+        line: 1,
+        reg: "b".to_string(),
+        amount: Expr::Int(5),
+        cond: Cond::Compare(Expr::Reg("a".to_string()), CmpOp::Gt, Expr::Int(1)),
+    });
+
+    // && binds tighter than ||, and * tighter than -
+    let reg = |name: &str| Box::new(Expr::Reg(name.to_string()));
+    let stmt = &program.stmts[1];
+    assert_eq!(stmt.line, 3);
+    assert_eq!(stmt.amount, Expr::Neg(Box::new(Expr::Binary(reg("a"), BinOp::Mul,
+        Box::new(Expr::Binary(reg("b"), BinOp::Sub, Box::new(Expr::Int(2))))))));
+    match stmt.cond {
+        Cond::Or(ref left, _) => match **left {
+            Cond::And(_, _) => (),
+            ref cond => panic!("Expected && inside ||, found {:?}", cond),
+        },
+        ref cond => panic!("Expected ||, found {:?}", cond),
+    }
+
+    // Each bad line gets its own diagnostic
+    let diagnostics = Program::parse("a inc 1 if\nb add 2 if a > 1\nc inc 3 if c > 1\nd inc (4 if d $ 1").unwrap_err();
+    let messages: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+    assert_eq!(messages, vec![
+        "line 1, column 11: Expected operand, found end of line",
+        "line 2, column 3: Expected 'inc' or 'dec', found 'add'",
+        "line 4, column 15: Unexpected character '$'",
+    ]);
+    assert_eq!(Program::parse("d inc (4 if d > 1").unwrap_err()[0].to_string(),
+        "line 1, column 10: Expected ')', found 'if'");
+}
+
+// ==== Interpreter ====
+
+pub(crate) struct Interpreter {
+    pub(crate) regs: HashMap<String, i64>,
+    pub(crate) max_value: i64, // largest value held by any register so far
+    pub(crate) history: Vec<i64>, // max_value after each statement
+    pub(crate) timeline: Option<Timeline>,
+}
+
+impl Interpreter {
+    pub(crate) fn new() -> Self {
+        Self { regs: HashMap::new(), max_value: 0, history: Vec::new(), timeline: None }
+    }
+
+    pub(crate) fn run(&mut self, program: &Program) -> Result<(), Diagnostic> {
+        for stmt in &program.stmts {
+            self.step(stmt)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn step(&mut self, stmt: &Stmt) -> Result<(), Diagnostic> {
+        let mut written = None;
+        if self.test(&stmt.cond, stmt.line)? {
+            let amount = self.eval(&stmt.amount, stmt.line)?;
+            let value = self.read(&stmt.reg).checked_add(amount)
+                .ok_or_else(|| overflow(stmt.line))?;
+            self.regs.insert(stmt.reg.clone(), value);
+            self.max_value = max(self.max_value, value);
+            written = Some(value);
+        }
+        self.history.push(self.max_value);
+
+        if let Some(ref mut timeline) = self.timeline {
+            timeline.record(stmt, written);
+        }
+        Ok(())
+    }
+
+    // Registers which were never written read as zero
+    pub(crate) fn read(&self, reg: &str) -> i64 {
+        *self.regs.get(reg).unwrap_or(&0)
+    }
+
+    // Largest value currently held by any register which has been written
+    pub(crate) fn largest(&self) -> Option<i64> {
+        self.regs.values().cloned().max()
+    }
+
+    fn eval(&self, expr: &Expr, line: usize) -> Result<i64, Diagnostic> {
+        let value = match *expr {
+            Expr::Int(int) => Some(int),
+            Expr::Reg(ref reg) => Some(self.read(reg)),
+            Expr::Neg(ref expr) => self.eval(expr, line)?.checked_neg(),
+            Expr::Binary(ref left, op, ref right) => {
+                let (left, right) = (self.eval(left, line)?, self.eval(right, line)?);
+                match op {
+                    BinOp::Add => left.checked_add(right),
+                    BinOp::Sub => left.checked_sub(right),
+                    BinOp::Mul => left.checked_mul(right),
+                }
+            },
+        };
+        value.ok_or_else(|| overflow(line))
+    }
+
+    fn test(&self, cond: &Cond, line: usize) -> Result<bool, Diagnostic> {
+        Ok(match *cond {
+            Cond::Compare(ref left, op, ref right) => {
+                let (left, right) = (self.eval(left, line)?, self.eval(right, line)?);
+                match op {
+                    CmpOp::Lt => left < right,
+                    CmpOp::Le => left <= right,
+                    CmpOp::Eq => left == right,
+                    CmpOp::Ne => left != right,
+                    CmpOp::Ge => left >= right,
+                    CmpOp::Gt => left > right,
+                }
+            },
+            Cond::And(ref left, ref right) => self.test(left, line)? && self.test(right, line)?,
+            Cond::Or(ref left, ref right) => self.test(left, line)? || self.test(right, line)?,
+        })
+    }
+}
+
+fn overflow(line: usize) -> Diagnostic {
+    Diagnostic::new(line, 0, "Arithmetic overflow".to_string())
+}
+
+#[test]
+fn test_day8_interpreter() {
+    let program = Program::parse("a inc 3 if b == 0\nb inc a * 2 if a > 1 && b < a\nc dec b - a if c == 0 || a < 0").unwrap();
+    let mut interpreter = Interpreter::new();
+    interpreter.run(&program).unwrap();
+    assert_eq!((interpreter.read("a"), interpreter.read("b"), interpreter.read("c")), (3, 6, -3));
+    assert_eq!(interpreter.history, vec![3, 6, 6]);
+    assert_eq!(interpreter.largest(), Some(6));
+
+    let program = Program::parse("a inc 9223372036854775807 if a == 0\nb inc 1 if a + 1 > 0").unwrap();
+    assert_eq!(Interpreter::new().run(&program).unwrap_err().to_string(), "line 2: Arithmetic overflow");
+}
+
+// ==== Timeline ====
+
+// A register write, and the statement which made it
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) struct Change {
+    pub(crate) step: usize, // index of the statement executed
+    pub(crate) line: usize,
+    pub(crate) reg: String,
+    pub(crate) value: i64,
+}
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub(crate) struct Timeline {
+    pub(crate) lines: Vec<usize>, // line of each statement executed
+    pub(crate) changes: Vec<Change>,
+}
+
+impl Timeline {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    fn record(&mut self, stmt: &Stmt, written: Option<i64>) {
+        if let Some(value) = written {
+            let step = self.lines.len();
+            self.changes.push(Change { step, line: stmt.line, reg: stmt.reg.clone(), value });
+        }
+        self.lines.push(stmt.line);
+    }
+
+    // Register names in the order they were first written
+    pub(crate) fn regs(&self) -> Vec<&str> {
+        let mut regs: Vec<&str> = Vec::new();
+        for change in &self.changes {
+            if !regs.contains(&change.reg.as_str()) {
+                regs.push(&change.reg);
+            }
+        }
+        regs
+    }
+
+    // Value of a register after the given step
+    pub(crate) fn value_at(&self, reg: &str, step: usize) -> i64 {
+        self.changes.iter()
+            .take_while(|change| change.step <= step)
+            .filter(|change| change.reg == reg)
+            .last()
+            .map_or(0, |change| change.value)
+    }
+
+    // The first write which took a register to its highest value
+    pub(crate) fn peak(&self, reg: &str) -> Option<&Change> {
+        self.changes.iter()
+            .filter(|change| change.reg == reg)
+            .min_by_key(|change| Reverse(change.value))
+    }
+
+    // The first write of the highest value held by any register
+    pub(crate) fn max_cause(&self) -> Option<&Change> {
+        self.changes.iter().min_by_key(|change| Reverse(change.value))
+    }
+
+    // One row per step with the line run, the register it changed, and every register's value after it
+    pub(crate) fn to_csv(&self) -> String {
+        let regs = self.regs();
+        let mut csv = format!("step,line,changed,{}\n", regs.join(","));
+
+        let mut values = vec![0; regs.len()];
+        let mut changes = self.changes.iter().peekable();
+        for (step, line) in self.lines.iter().enumerate() {
+            let mut changed = "";
+            if let Some(change) = changes.next_if(|change| change.step == step) {
+                let index = regs.iter().position(|&reg| reg == change.reg).unwrap();
+                values[index] = change.value;
+                changed = &change.reg;
+            }
+
+            let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+            csv.push_str(&format!("{},{},{},{}\n", step, line, changed, values.join(",")));
+        }
+
+        csv
+    }
+}
+
+#[test]
+fn test_day8_timeline() {
+    let program = Program::parse("b inc 5 if a > 1\na inc 1 if b < 5\nc dec -10 if a >= 1\nc inc -20 if c == 10").unwrap();
+    let mut interpreter = Interpreter::new();
+    interpreter.timeline = Some(Timeline::new());
+    interpreter.run(&program).unwrap();
+
+    let timeline = interpreter.timeline.unwrap();
+    assert_eq!(timeline.lines, vec![1, 2, 3, 4]);
+    assert_eq!(timeline.regs(), vec!["a", "c"]);
+    assert_eq!(timeline.value_at("c", 2), 10);
+    assert_eq!(timeline.value_at("c", 3), -10);
+    assert_eq!(timeline.value_at("b", 3), 0);
+    assert_eq!(timeline.peak("c").map(|change| (change.step, change.line, change.value)), Some((2, 3, 10)));
+    assert_eq!(timeline.peak("b"), None);
+    assert_eq!(timeline.max_cause().map(|change| (change.reg.as_str(), change.line)), Some(("c", 3)));
+    assert_eq!(timeline.to_csv(), "step,line,changed,a,c\n0,1,,0,0\n1,2,a,1,0\n2,3,c,1,10\n3,4,c,1,-10\n");
+}
+
+// ==== Exercises and tests ====
+
+fn run(input: &str, timeline: bool) -> Result<Interpreter, io::Error> {
+    let program = Program::parse(input).map_err(|diagnostics| to_error(&diagnostics))?;
+    let mut interpreter = Interpreter::new();
+    if timeline {
+        interpreter.timeline = Some(Timeline::new());
+    }
+    interpreter.run(&program).map_err(|diagnostic| to_error(&[diagnostic]))?;
+    Ok(interpreter)
+}
+
+fn dewit(input: &str) -> Result<(i64, i64), io::Error> {
+    let interpreter = run(input, false)?;
+
     // Return the current largest value and the largest value seen
-    (*regs.values().max().unwrap(), max_value)
+    Ok((interpreter.largest().unwrap_or(0), interpreter.max_value))
 }
 
 #[test]
@@ -62,7 +635,7 @@ fn test_day8() {
 a inc 1 if b < 5
 c dec -10 if a >= 1
 c inc -20 if c == 10";
-    assert_eq!(dewit(&input), (1, 10));
+    assert_eq!(dewit(&input).unwrap(), (1, 10));
 }
 
 pub fn day8(args: &mut env::Args) -> Result<(), io::Error> {
@@ -72,8 +645,28 @@ pub fn day8(args: &mut env::Args) -> Result<(), io::Error> {
         fs::read_to_string(name)?
     };
 
-    let (part1, part2) = dewit(&input);
+    let (part1, part2) = dewit(&input)?;
     println!("Part 1: {}\nPart 2: {}", part1, part2);
 
+    // Optionally export the register timeline, or report where values peaked
+    match args.next().as_deref() {
+        Some("csv") => print!("{}", run(&input, true)?.timeline.unwrap().to_csv()),
+        Some("peak") => {
+            let timeline = run(&input, true)?.timeline.unwrap();
+            if let Some(change) = timeline.max_cause() {
+                println!("Max value {} set in {} by line {} (step {})",
+                    change.value, change.reg, change.line, change.step);
+            }
+            for reg in args {
+                match timeline.peak(&reg) {
+                    Some(change) => println!("Register {} peaked at {} on line {} (step {}), ending at {}",
+                        reg, change.value, change.line, change.step, timeline.value_at(&reg, timeline.lines.len())),
+                    None => println!("Register {} was never written", reg),
+                }
+            }
+        },
+        _ => (),
+    }
+
     Ok(())
 }
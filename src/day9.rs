@@ -1,37 +1,454 @@
 use std::fs;
 use std::env;
 use std::io;
+use std::fmt;
+use std::io::{Read, Write};
+use std::collections::BTreeMap;
 
-fn dewit(input: &str) -> (u32, u32) {
-    // Mutable state for filter_map below
-    let mut escape = false;
-    let mut garbage = false;
+// ==== Stream events ====
+
+// Byte offsets into a stream, with end exclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: u64,
+    pub end: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Open(u64),
+    Close(u64),
+    // Count of characters inside the garbage, and of characters cancelled with '!'
+    Garbage { span: Span, count: u64, cancelled: u64 },
+}
+
+#[derive(Debug)]
+pub enum StreamError {
+    Io(io::Error),
+    UnexpectedClose(u64),
+    UnclosedGroup(u64),
+    UnterminatedGarbage(u64),
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StreamError::Io(e) => write!(f, "{}", e),
+            StreamError::UnexpectedClose(pos) => write!(f, "Unexpected '}}' at byte {}", pos),
+            StreamError::UnclosedGroup(pos) => write!(f, "Group opened at byte {} is never closed", pos),
+            StreamError::UnterminatedGarbage(pos) => write!(f, "Garbage starting at byte {} is never terminated", pos),
+        }
+    }
+}
+
+impl std::error::Error for StreamError {}
+
+impl From<StreamError> for io::Error {
+    fn from(error: StreamError) -> io::Error {
+        match error {
+            StreamError::Io(e) => e,
+            error => io::Error::new(io::ErrorKind::InvalidData, error),
+        }
+    }
+}
+
+// The state machine for discarding garbage, fed one byte at a time; counts are in characters,
+// as UTF-8 continuation bytes belong to the character before them
+#[derive(Default)]
+struct Machine {
+    escape: bool,
+    garbage: Option<u64>,
+    count: u64,
+    cancelled: u64,
+}
+
+impl Machine {
+    fn feed(&mut self, pos: u64, byte: u8) -> Option<Event> {
+        match (self.escape, self.garbage, byte) {
+            (_, _, 0x80..=0xbf) => None,
+            (true, _, _) => { self.escape = false; self.cancelled += 1; None },
+            (_, Some(_), b'!') => { self.escape = true; None },
+            (_, Some(start), b'>') => {
+                let event = Event::Garbage { span: Span { start, end: pos + 1 }, count: self.count, cancelled: self.cancelled };
+                *self = Machine::default();
+                Some(event)
+            },
+            (_, Some(_), _) => { self.count += 1; None },
+            (_, _, b'<') => { self.garbage = Some(pos); None },
+            (_, _, b'{') => Some(Event::Open(pos)),
+            (_, _, b'}') => Some(Event::Close(pos)),
+            (_, _, _) => None,
+        }
+    }
+}
+
+// Events from a stream, read in chunks, checking that groups balance and garbage ends
+pub struct Events<R> {
+    reader: R,
+    buf: Vec<u8>,
+    next: usize,
+    len: usize,
+    offset: u64,
+    machine: Machine,
+    opened: Vec<u64>,
+    done: bool,
+    // Raw bytes of the latest garbage, if we're keeping them
+    text: Option<Vec<u8>>,
+}
+
+impl<R: Read> Events<R> {
+    pub fn new(reader: R) -> Self {
+        Events {
+            reader,
+            buf: vec![0; 1 << 16],
+            next: 0,
+            len: 0,
+            offset: 0,
+            machine: Machine::default(),
+            opened: Vec::new(),
+            done: false,
+            text: None,
+        }
+    }
+
+    // Also keep the raw bytes of each garbage, including its '<', '>' and any escapes
+    pub fn keeping_garbage(reader: R) -> Self {
+        Events { text: Some(Vec::new()), ..Events::new(reader) }
+    }
+
+    // Raw bytes of the latest garbage event, if they're being kept
+    pub fn garbage(&self) -> Option<&[u8]> {
+        self.text.as_deref()
+    }
+
+    // Check for anything left open once the stream ends
+    fn finish(&mut self) -> Option<StreamError> {
+        if let Some(start) = self.machine.garbage {
+            Some(StreamError::UnterminatedGarbage(start))
+        } else {
+            self.opened.first().map(|&pos| StreamError::UnclosedGroup(pos))
+        }
+    }
+}
+
+impl<R: Read> Iterator for Events<R> {
+    type Item = Result<Event, StreamError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            // Refill the buffer once it's used up
+            if self.next == self.len {
+                self.offset += self.len as u64;
+                self.next = 0;
+                self.len = match self.reader.read(&mut self.buf) {
+                    Ok(0) => { self.done = true; return self.finish().map(Err) },
+                    Ok(len) => len,
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => 0,
+                    Err(e) => { self.done = true; return Some(Err(StreamError::Io(e))) },
+                };
+                continue;
+            }
+
+            let pos = self.offset + self.next as u64;
+            let byte = self.buf[self.next];
+            self.next += 1;
+            let was_garbage = self.machine.garbage.is_some();
+            let event = self.machine.feed(pos, byte);
+            if let Some(text) = self.text.as_mut() {
+                if !was_garbage && self.machine.garbage.is_some() {
+                    text.clear();
+                }
+                if was_garbage || self.machine.garbage.is_some() {
+                    text.push(byte);
+                }
+            }
+            match event {
+                Some(Event::Open(pos)) => self.opened.push(pos),
+                Some(Event::Close(pos)) if self.opened.pop().is_none() => {
+                    self.done = true;
+                    return Some(Err(StreamError::UnexpectedClose(pos)));
+                },
+                _ => (),
+            }
+            if event.is_some() {
+                return event.map(Ok);
+            }
+        }
+        None
+    }
+}
+
+#[test]
+fn test_day9_events() {
+    let events: Vec<Event> = Events::new("{<a!>b>,{}}".as_bytes()).map(Result::unwrap).collect();
+    assert_eq!(events, vec![
+        Event::Open(0),
+        Event::Garbage { span: Span { start: 1, end: 7 }, count: 2, cancelled: 1 },
+        Event::Open(8),
+        Event::Close(9),
+        Event::Close(10),
+    ]);
+
+    let error = |input: &str| Events::new(input.as_bytes()).find_map(Result::err).map(|e| e.to_string());
+    assert_eq!(error("{{}}}"), Some("Unexpected '}' at byte 4".to_string()));
+    assert_eq!(error("{{<>}"), Some("Group opened at byte 0 is never closed".to_string()));
+    assert_eq!(error("{<!>}"), Some("Garbage starting at byte 1 is never terminated".to_string()));
+    assert_eq!(error("{<>}"), None);
+
+    // Garbage is counted and cancelled by character, not by byte
+    let garbage = |input: &str| Events::new(input.as_bytes()).map(Result::unwrap).nth(1);
+    assert_eq!(garbage("{<é!üñ>}"), Some(Event::Garbage { span: Span { start: 1, end: 10 }, count: 2, cancelled: 1 }));
+    assert_eq!(garbage("{<日本>}"), Some(Event::Garbage { span: Span { start: 1, end: 9 }, count: 2, cancelled: 0 }));
+
+    let mut events = Events::keeping_garbage("{<a!>>,<>}".as_bytes());
+    events.next();
+    events.next();
+    assert_eq!(events.garbage(), Some(&b"<a!>>"[..]));
+    events.next();
+    assert_eq!(events.garbage(), Some(&b"<>"[..]));
+
+    // Chunk boundaries don't matter
+    let input = "{<ab>,{!}}".repeat(20000);
+    assert_eq!(Events::new(input.as_bytes()).filter(Result::is_ok).count(), 20000 * 5);
+}
+
+// ==== Syntax trees ====
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node {
+    Group { span: Span, children: Vec<Node> },
+    Garbage { span: Span, count: u64, cancelled: u64 },
+}
+
+impl Node {
+    pub fn span(&self) -> Span {
+        match self {
+            Node::Group { span, .. } | Node::Garbage { span, .. } => *span,
+        }
+    }
+}
+
+// Build a tree from each top level group or garbage in a stream
+pub fn parse<R: Read>(reader: R) -> Result<Vec<Node>, StreamError> {
+    // The children of each open group, with the root level at the bottom
+    let mut stack: Vec<(u64, Vec<Node>)> = vec![(0, Vec::new())];
+    for event in Events::new(reader) {
+        match event? {
+            Event::Open(pos) => stack.push((pos, Vec::new())),
+            Event::Close(pos) => {
+                let (start, children) = stack.pop().unwrap();
+                let group = Node::Group { span: Span { start, end: pos + 1 }, children };
+                stack.last_mut().unwrap().1.push(group);
+            },
+            Event::Garbage { span, count, cancelled } =>
+                stack.last_mut().unwrap().1.push(Node::Garbage { span, count, cancelled }),
+        }
+    }
+    Ok(stack.pop().unwrap().1)
+}
+
+#[test]
+fn test_day9_parse() {
+    let span = |start, end| Span { start, end };
+    assert_eq!(parse("{{<!!>},{}}\n".as_bytes()).unwrap(), vec![
+        Node::Group { span: span(0, 11), children: vec![
+            Node::Group { span: span(1, 7), children: vec![
+                Node::Garbage { span: span(2, 6), count: 0, cancelled: 1 },
+            ] },
+            Node::Group { span: span(8, 10), children: vec![] },
+        ] },
+    ]);
+    assert_eq!(parse("<a>".as_bytes()).unwrap()[0].span(), span(0, 3));
+    assert!(parse("{{}".as_bytes()).is_err());
+}
+
+// ==== Formatting and statistics ====
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GarbageStyle {
+    // Leave garbage out entirely
+    Strip,
+    // Keep each garbage verbatim, escapes and all, so the output parses the same
+    Keep,
+}
+
+// Write a stream with one group or garbage per line, indenting by depth
+pub fn format<R: Read, W: Write>(reader: R, out: &mut W, style: GarbageStyle) -> Result<(), io::Error> {
+    let mut events = Events::keeping_garbage(reader);
+    let mut depth = 0;
+    let mut empty = true;
+
+    // Separate from the previous item, with commas inside groups
+    let start = |out: &mut W, depth: usize, empty: bool| -> Result<(), io::Error> {
+        match (depth, empty) {
+            (0, true) => (),
+            (0, false) => writeln!(out)?,
+            (_, true) => writeln!(out)?,
+            (_, false) => writeln!(out, ",")?,
+        }
+        write!(out, "{:indent$}", "", indent = depth * 4)
+    };
+
+    while let Some(event) = events.next() {
+        match event? {
+            Event::Open(_) => {
+                start(out, depth, empty)?;
+                write!(out, "{{")?;
+                depth += 1;
+                empty = true;
+            },
+            Event::Close(_) => {
+                depth -= 1;
+                if empty {
+                    write!(out, "}}")?;
+                } else {
+                    write!(out, "\n{:indent$}}}", "", indent = depth * 4)?;
+                }
+                empty = false;
+            },
+            Event::Garbage { .. } if style == GarbageStyle::Strip => (),
+            Event::Garbage { .. } => {
+                start(out, depth, empty)?;
+                out.write_all(events.garbage().unwrap())?;
+                empty = false;
+            },
+        }
+    }
+
+    if !empty {
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_day9_format() {
+    let format = |input: &str, style| {
+        let mut out = Vec::new();
+        format(input.as_bytes(), &mut out, style).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+
+    let input = "{{<a!>b>},{},<{}>}";
+    assert_eq!(format(input, GarbageStyle::Keep), "\
+{
+    {
+        <a!>b>
+    },
+    {},
+    <{}>
+}
+");
+    assert_eq!(format(input, GarbageStyle::Strip), "{\n    {},\n    {}\n}\n");
+
+    // Formatting doesn't change the score or garbage count
+    let input = "{{<a!>},{<a!>},{<a!>},{<ab>}}";
+    assert_eq!(dewit(format(input, GarbageStyle::Keep).as_bytes()).unwrap(), dewit(input.as_bytes()).unwrap());
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub groups: u64,
+    pub score: u64,
+    pub max_depth: u64,
+    // How many groups directly hold each number of groups and garbage
+    pub sizes: BTreeMap<u64, u64>,
+    pub garbage: u64,
+    // The garbage with the most characters, and that count
+    pub longest: Option<(Span, u64)>,
+    pub cancelled: u64,
+}
+
+impl Stats {
+    pub fn collect<R: Read>(reader: R) -> Result<Stats, StreamError> {
+        let mut stats = Stats::default();
+
+        // Count of items in each open group
+        let mut sizes: Vec<u64> = Vec::new();
+        let item = |sizes: &mut Vec<u64>| if let Some(size) = sizes.last_mut() { *size += 1 };
+
+        for event in Events::new(reader) {
+            match event? {
+                Event::Open(_) => {
+                    item(&mut sizes);
+                    sizes.push(0);
+                    stats.groups += 1;
+                    stats.score += sizes.len() as u64;
+                    stats.max_depth = stats.max_depth.max(sizes.len() as u64);
+                },
+                Event::Close(_) => *stats.sizes.entry(sizes.pop().unwrap()).or_insert(0) += 1,
+                Event::Garbage { span, count, cancelled } => {
+                    item(&mut sizes);
+                    stats.garbage += count;
+                    stats.cancelled += cancelled;
+                    if stats.longest.is_none_or(|(_, longest)| count > longest) {
+                        stats.longest = Some((span, count));
+                    }
+                },
+            }
+        }
+
+        Ok(stats)
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Groups: {} (score {}, max depth {})", self.groups, self.score, self.max_depth)?;
+        writeln!(f, "Garbage: {} characters, {} cancelled", self.garbage, self.cancelled)?;
+        if let Some((span, count)) = self.longest {
+            writeln!(f, "Longest garbage: {} characters at bytes {}..{}", count, span.start, span.end)?;
+        }
+        writeln!(f, "Group sizes:")?;
+        let most = self.sizes.values().cloned().max().unwrap_or(0);
+        for (size, &groups) in &self.sizes {
+            let bar = "#".repeat(((groups * 40).div_ceil(most.max(1))) as usize);
+            writeln!(f, "{:>6} {:>8} {}", size, groups, bar)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_day9_stats() {
+    let input = "{{<ab>},{<a!>},{<!!>},{}}";
+    let stats = Stats::collect(input.as_bytes()).unwrap();
+    assert_eq!(stats, Stats {
+        groups: 4,
+        score: 7,
+        max_depth: 2,
+        sizes: [(0, 1), (1, 2), (3, 1)].iter().cloned().collect(),
+        garbage: 7,
+        longest: Some((Span { start: 9, end: 20 }, 5)),
+        cancelled: 2,
+    });
+    assert_eq!(dewit(input.as_bytes()).unwrap(), (stats.score, stats.garbage));
+    assert!(stats.to_string().contains("Longest garbage: 5 characters at bytes 9..20\n"));
+}
+
+// ==== Exercises and tests ====
+
+// Total score of every group, and count of characters in garbage
+fn dewit<R: Read>(reader: R) -> Result<(u64, u64), StreamError> {
+    let mut depth = 0;
+    let mut score = 0;
     let mut count = 0;
 
-    // Feed each char through a state machine producing the score
-    let (_, score) = input
-        .chars()
-        // A state machine for discarding garbage
-        .filter_map(|c| match (escape, garbage, c) {
-            (true, _, _) => { escape = false; None },
-            (_, true, '!') => { escape = true; None },
-            (_, true, '>') => { garbage = false; None },
-            (_, true, _) => { count += 1; None },
-            (_, _, '<') => { garbage = true; None },
-            (_, _, c) => Some(c),
-        })
-        // A (much simpler) state machine for scoring groups
-        .fold((0, 0), |(depth, score), c| match c {
-            '{' => (depth + 1, score + depth + 1),
-            '}' => (depth - 1, score),
-            _ => (depth, score),
-        });
-
-    (score, count)
+    for event in Events::new(reader) {
+        match event? {
+            Event::Open(_) => { depth += 1; score += depth; },
+            Event::Close(_) => depth -= 1,
+            Event::Garbage { count: n, .. } => count += n,
+        }
+    }
+
+    Ok((score, count))
 }
 
 #[test]
 fn test_day9() {
+    let dewit = |input: &str| dewit(input.as_bytes()).unwrap();
+
     // Test garbage
     assert_eq!(dewit("<>"), (0, 0));
     assert_eq!(dewit("<random characters>"), (0, 17));
@@ -53,14 +470,38 @@ fn test_day9() {
 }
 
 pub fn day9(args: &mut env::Args) -> Result<(), io::Error> {
-    // Read from file in first arg or default to input.txt
-    let input = {
-        let name = args.next().unwrap_or("input/day9.txt".to_string());
-        fs::read_to_string(name)?
-    };
+    // Stream from file in first arg or default to input.txt
+    let name = args.next().unwrap_or("input/day9.txt".to_string());
 
-    let (part1, part2) = dewit(&input);
+    let (part1, part2) = dewit(fs::File::open(&name)?)?;
     println!("Part 1: {}\nPart 2: {}", part1, part2);
 
+    // Optionally print the outline of each group and garbage, reformat the stream, or report stats
+    match args.next().as_deref() {
+        Some("fmt") => {
+            let style = match args.next().as_deref() {
+                Some("strip") => GarbageStyle::Strip,
+                _ => GarbageStyle::Keep,
+            };
+            format(fs::File::open(&name)?, &mut io::stdout().lock(), style)?;
+        },
+        Some("stats") => print!("{}", Stats::collect(fs::File::open(&name)?)?),
+        Some("tree") => {
+            let mut stack: Vec<(usize, Node)> = parse(fs::File::open(&name)?)?.into_iter().rev().map(|node| (0, node)).collect();
+            while let Some((depth, node)) = stack.pop() {
+                let Span { start, end } = node.span();
+                match node {
+                    Node::Group { children, .. } => {
+                        println!("{:indent$}group {}..{} ({} children)", "", start, end, children.len(), indent = depth * 2);
+                        stack.extend(children.into_iter().rev().map(|child| (depth + 1, child)));
+                    },
+                    Node::Garbage { count, cancelled, .. } => println!("{:indent$}garbage {}..{} ({} chars, {} cancelled)",
+                        "", start, end, count, cancelled, indent = depth * 2),
+                }
+            }
+        },
+        _ => (),
+    }
+
     Ok(())
 }
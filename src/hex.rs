@@ -0,0 +1,340 @@
+// A library for any hex grid simulation, so not every part is used by the puzzles
+#![allow(dead_code)]
+
+use std::ops;
+
+// Hex grid geometry in doubled coordinates, for flat-topped hexes: moving n adds 2 to y, and
+// moving ne adds 1 to both x and y, so x + y is always even
+
+// ==== Directions ====
+
+// Directions clockwise from north
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Direction {
+    N,
+    NE,
+    SE,
+    S,
+    SW,
+    NW,
+}
+
+impl Direction {
+    pub const ALL: [Direction; 6] = [Direction::N, Direction::NE, Direction::SE, Direction::S, Direction::SW, Direction::NW];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Direction::ALL.iter().cloned().find(|dir| dir.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Direction::N => "n",
+            Direction::NE => "ne",
+            Direction::SE => "se",
+            Direction::S => "s",
+            Direction::SW => "sw",
+            Direction::NW => "nw",
+        }
+    }
+
+    pub fn step(self) -> HexCoord {
+        match self {
+            Direction::N => HexCoord(0, 2),
+            Direction::NE => HexCoord(1, 1),
+            Direction::SE => HexCoord(1, -1),
+            Direction::S => HexCoord(0, -2),
+            Direction::SW => HexCoord(-1, -1),
+            Direction::NW => HexCoord(-1, 1),
+        }
+    }
+
+    // Turn clockwise by sixths of a full turn, or anticlockwise if negative
+    pub fn rotate(self, sixths: isize) -> Self {
+        Direction::ALL[(self as isize + sixths).rem_euclid(6) as usize]
+    }
+
+    pub fn opposite(self) -> Self {
+        self.rotate(3)
+    }
+}
+
+// ==== Coordinates ====
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct HexCoord(pub isize, pub isize);
+
+// Cube coordinates, where q + r + s = 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Cube {
+    pub q: isize,
+    pub r: isize,
+    pub s: isize,
+}
+
+// Axial coordinates, which are cube coordinates without s
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Axial {
+    pub q: isize,
+    pub r: isize,
+}
+
+impl HexCoord {
+    pub const ORIGIN: HexCoord = HexCoord(0, 0);
+
+    pub fn from_cube(cube: Cube) -> Self {
+        debug_assert_eq!(cube.q + cube.r + cube.s, 0);
+        HexCoord(cube.q, 2 * cube.r + cube.q)
+    }
+
+    pub fn to_cube(self) -> Cube {
+        let HexCoord(x, y) = self;
+        debug_assert_eq!((x + y) % 2, 0);
+        let r = (y - x) / 2;
+        Cube { q: x, r, s: -x - r }
+    }
+
+    pub fn from_axial(axial: Axial) -> Self {
+        HexCoord(axial.q, 2 * axial.r + axial.q)
+    }
+
+    pub fn to_axial(self) -> Axial {
+        let Cube { q, r, .. } = self.to_cube();
+        Axial { q, r }
+    }
+
+    // Steps from the origin
+    pub fn distance(&self) -> usize {
+        let HexCoord(x, y) = self;
+        let (x, y) = (x.abs(), y.abs());
+        // Y must be greater than X to contribute to distance
+        let y = (y - x).max(0) / 2;
+        (x + y) as usize
+    }
+
+    // Steps between any two coordinates
+    pub fn distance_to(self, other: HexCoord) -> usize {
+        (other - self).distance()
+    }
+
+    // Shortest path to another coordinate, using at most two neighbouring directions, in the
+    // order of Direction::ALL so that equal displacements give equal paths
+    pub fn path_to(self, other: HexCoord) -> Vec<Direction> {
+        let HexCoord(x, y) = other - self;
+        let (up, down) = if x < 0 { (Direction::NW, Direction::SW) } else { (Direction::NE, Direction::SE) };
+
+        // Every column needs a diagonal step, and any remaining rows need vertical steps
+        let columns = x.unsigned_abs();
+        let mut path = Vec::with_capacity(self.distance_to(other));
+        if y.unsigned_abs() >= columns {
+            let vertical = if y < 0 { Direction::S } else { Direction::N };
+            let diagonal = if y < 0 { down } else { up };
+            path.extend(std::iter::repeat_n(vertical, (y.unsigned_abs() - columns) / 2));
+            path.extend(std::iter::repeat_n(diagonal, columns));
+        } else {
+            let ups = ((x.abs() + y) / 2) as usize;
+            path.extend(std::iter::repeat_n(up, ups));
+            path.extend(std::iter::repeat_n(down, columns - ups));
+        }
+
+        path.sort();
+        path
+    }
+
+    pub fn neighbour(self, dir: Direction) -> HexCoord {
+        self + dir.step()
+    }
+
+    // Neighbours clockwise from north
+    pub fn neighbours(self) -> impl Iterator<Item = HexCoord> {
+        Direction::ALL.iter().map(move |&dir| self.neighbour(dir))
+    }
+
+    // Coordinates at exactly radius steps, clockwise from the one due north
+    pub fn ring(self, radius: usize) -> Vec<HexCoord> {
+        if radius == 0 {
+            return vec![self];
+        }
+
+        let mut ring = Vec::with_capacity(6 * radius);
+        let mut pos = self + Direction::N.step() * radius as isize;
+        for &dir in &Direction::ALL {
+            // Walk each side, turning two sixths from each corner
+            for _ in 0..radius {
+                ring.push(pos);
+                pos = pos.neighbour(dir.rotate(2));
+            }
+        }
+        ring
+    }
+
+    // Coordinates within radius steps, ring by ring outwards
+    pub fn spiral(self, radius: usize) -> Vec<HexCoord> {
+        (0..=radius).flat_map(|r| self.ring(r)).collect()
+    }
+
+    // Each coordinate along a straight line to another, including both ends
+    pub fn line_to(self, other: HexCoord) -> Vec<HexCoord> {
+        let n = self.distance_to(other);
+        let (a, b) = (self.to_cube(), other.to_cube());
+
+        // Nudge off of hex edges, so ties round the same way along the line
+        let lerp = |a: isize, b: isize, t: f64, nudge: f64| a as f64 + (b - a) as f64 * t + nudge;
+        (0..=n)
+            .map(|i| {
+                let t = if n == 0 { 0.0 } else { i as f64 / n as f64 };
+                round_cube(lerp(a.q, b.q, t, 1e-6), lerp(a.r, b.r, t, 2e-6), lerp(a.s, b.s, t, -3e-6))
+            })
+            .collect()
+    }
+
+    // Rotate about the origin by sixths of a turn clockwise, or anticlockwise if negative
+    pub fn rotate(self, sixths: isize) -> HexCoord {
+        let Cube { mut q, mut r, mut s } = self.to_cube();
+        for _ in 0..sixths.rem_euclid(6) {
+            let (nq, nr, ns) = (-s, -q, -r);
+            q = nq;
+            r = nr;
+            s = ns;
+        }
+        HexCoord::from_cube(Cube { q, r, s })
+    }
+
+    pub fn rotate_about(self, center: HexCoord, sixths: isize) -> HexCoord {
+        (self - center).rotate(sixths) + center
+    }
+
+    // Mirror across the line through the origin along a direction (and its opposite)
+    pub fn reflect(self, axis: Direction) -> HexCoord {
+        let sixths = axis as isize;
+        let HexCoord(x, y) = self.rotate(-sixths);
+        HexCoord(-x, y).rotate(sixths)
+    }
+}
+
+// Round fractional cube coordinates to the nearest hex, fixing up whichever rounded furthest
+fn round_cube(q: f64, r: f64, s: f64) -> HexCoord {
+    let (mut rq, mut rr, rs) = (q.round(), r.round(), s.round());
+    let (dq, dr, ds) = ((rq - q).abs(), (rr - r).abs(), (rs - s).abs());
+    if dq > dr && dq > ds {
+        rq = -rr - rs;
+    } else if dr > ds {
+        rr = -rq - rs;
+    }
+    let (q, r) = (rq as isize, rr as isize);
+    HexCoord::from_cube(Cube { q, r, s: -q - r })
+}
+
+impl ops::Add for HexCoord {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        HexCoord(self.0 + other.0, self.1 + other.1)
+    }
+}
+
+impl ops::Sub for HexCoord {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        HexCoord(self.0 - other.0, self.1 - other.1)
+    }
+}
+
+impl ops::Neg for HexCoord {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        HexCoord(-self.0, -self.1)
+    }
+}
+
+impl ops::Mul<isize> for HexCoord {
+    type Output = Self;
+
+    fn mul(self, n: isize) -> Self {
+        HexCoord(self.0 * n, self.1 * n)
+    }
+}
+
+#[test]
+fn test_hex_conversions() {
+    for pos in HexCoord(3, -1).spiral(3) {
+        assert_eq!(HexCoord::from_cube(pos.to_cube()), pos);
+        assert_eq!(HexCoord::from_axial(pos.to_axial()), pos);
+        let Cube { q, r, s } = pos.to_cube();
+        assert_eq!(q + r + s, 0);
+        assert_eq!(pos.distance(), q.abs().max(r.abs()).max(s.abs()) as usize);
+    }
+    assert_eq!(HexCoord(1, 1).to_cube(), Cube { q: 1, r: 0, s: -1 });
+    assert_eq!(HexCoord(0, 2).to_axial(), Axial { q: 0, r: 1 });
+}
+
+#[test]
+fn test_hex_neighbours() {
+    let pos = HexCoord(2, 4);
+    let neighbours: Vec<HexCoord> = pos.neighbours().collect();
+    assert_eq!(neighbours, pos.ring(1));
+    assert!(neighbours.iter().all(|&n| pos.distance_to(n) == 1));
+    assert_eq!(Direction::from_name("sw"), Some(Direction::SW));
+    assert_eq!(Direction::NW.rotate(1), Direction::N);
+    assert_eq!(Direction::NE.opposite(), Direction::SW);
+
+    // Rings have 6 * radius coordinates, all at the same distance
+    assert_eq!(pos.ring(0), vec![pos]);
+    for radius in 1..5 {
+        let ring = pos.ring(radius);
+        assert_eq!(ring.len(), 6 * radius);
+        assert!(ring.iter().all(|&n| pos.distance_to(n) == radius));
+    }
+    assert_eq!(pos.spiral(3).len(), 1 + 6 + 12 + 18);
+}
+
+#[test]
+fn test_hex_paths() {
+    use self::Direction::*;
+    assert_eq!(HexCoord::ORIGIN.path_to(HexCoord::ORIGIN), vec![]);
+    assert_eq!(HexCoord::ORIGIN.path_to(HexCoord(2, 6)), vec![N, N, NE, NE]);
+    assert_eq!(HexCoord::ORIGIN.path_to(HexCoord(-3, 1)), vec![SW, NW, NW]);
+    assert_eq!(HexCoord(1, 1).path_to(HexCoord(1, -5)), vec![S, S, S]);
+
+    // Paths are as long as the distance, and lead to the other coordinate
+    let start = HexCoord(2, -4);
+    for end in start.spiral(4) {
+        let path = start.path_to(end);
+        assert_eq!(path.len(), start.distance_to(end));
+        assert_eq!(path.iter().fold(start, |pos, &dir| pos.neighbour(dir)), end);
+    }
+}
+
+#[test]
+fn test_hex_lines() {
+    assert_eq!(HexCoord::ORIGIN.line_to(HexCoord::ORIGIN), vec![HexCoord::ORIGIN]);
+    assert_eq!(HexCoord::ORIGIN.line_to(HexCoord(0, 6)), vec![HexCoord(0, 0), HexCoord(0, 2), HexCoord(0, 4), HexCoord(0, 6)]);
+
+    // Each step along a line is to a neighbour
+    for &end in &[HexCoord(5, -3), HexCoord(-4, 8), HexCoord(7, 7)] {
+        let line = HexCoord(1, 1).line_to(end);
+        assert_eq!(line.len(), HexCoord(1, 1).distance_to(end) + 1);
+        assert!(line.windows(2).all(|pair| pair[0].distance_to(pair[1]) == 1));
+        assert_eq!(line.last(), Some(&end));
+    }
+}
+
+#[test]
+fn test_hex_transforms() {
+    // Rotating a direction's step is the same as rotating the direction
+    for &dir in &Direction::ALL {
+        assert_eq!(dir.step().rotate(1), dir.rotate(1).step());
+        assert_eq!(dir.step().rotate(-2), dir.rotate(-2).step());
+        assert_eq!(dir.step().reflect(dir), dir.step());
+        assert_eq!(dir.step().reflect(dir.rotate(-1)), dir.rotate(-2).step());
+    }
+
+    let pos = HexCoord(3, 5);
+    assert_eq!(pos.rotate(6), pos);
+    assert_eq!(pos.rotate(3), -pos);
+    assert_eq!(pos.reflect(Direction::N), HexCoord(-3, 5));
+    assert_eq!(pos.reflect(Direction::SE).reflect(Direction::SE), pos);
+    assert_eq!(pos.rotate_about(HexCoord(1, 1), 3), HexCoord(-1, -3));
+    assert_eq!(pos.rotate(2).distance(), pos.distance());
+}
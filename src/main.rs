@@ -24,6 +24,9 @@ mod day23;
 mod day24;
 mod day25;
 
+mod cycle;
+pub mod hex;
+
 use std::error::Error;
 use std::process::exit;
 
@@ -34,9 +37,10 @@ fn main() {
         .next()
         .expect("expect application name");
 
-    let day = args
+    let command = args
         .next()
-        .unwrap_or("0".to_string())
+        .unwrap_or("0".to_string());
+    let day = command
         .parse()
         .unwrap_or(0);
 
@@ -66,8 +70,10 @@ fn main() {
         23 => day23::day23(&mut args),
         24 => day24::day24(&mut args),
         25 => day25::day25(&mut args),
+        _ if command == "hash" => day10::hash(&mut args),
         _ => {
             println!("Usage: {} day", name);
+            println!("       {} hash [--size N] [--rounds N] [--suffix N,..] [--block N] [file..]", name);
             exit(1);
         }
     };